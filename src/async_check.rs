@@ -0,0 +1,173 @@
+//! Checks on a tokio runtime, enabled with the `async` feature.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use tokio::net::{self, TcpStream, ToSocketAddrs};
+use tokio::task;
+use tokio::time;
+use tokio_openssl::SslStream;
+
+use crate::error::{Error, Result};
+use crate::{no_addresses, Checker, SslExpiration, Target};
+
+impl SslExpiration {
+    /// Like `from_domain_name`, without blocking the runtime.
+    pub async fn from_domain_name_async(domain: &str) -> Result<SslExpiration> {
+        Checker::new().check_domain_async(domain).await
+    }
+
+    /// Like `from_addr`, without blocking the runtime.
+    pub async fn from_addr_async<A: ToSocketAddrs>(addr: A) -> Result<SslExpiration> {
+        Checker::new().check_async(addr, None).await
+    }
+
+    /// Like `from_addr_with_server_name`, without blocking the runtime.
+    pub async fn from_addr_with_server_name_async<A: ToSocketAddrs>(addr: A,
+                                                                    server_name: &str)
+                                                                    -> Result<SslExpiration> {
+        Checker::new().check_async(addr, Some(server_name)).await
+    }
+}
+
+impl Checker {
+    /// Like `check_domain`, without blocking the runtime.
+    ///
+    /// The timeouts apply differently than with `check_domain`, see
+    /// `check_async`.
+    pub async fn check_domain_async(&self, domain: &str) -> Result<SslExpiration> {
+        let port = self.port(&Target::new(domain, None));
+        self.check_async((domain, port), Some(domain)).await
+    }
+
+    /// Like `check`, without blocking the runtime.
+    ///
+    /// STARTTLS negotiations are plain blocking I/O and run on the blocking
+    /// thread pool of the runtime.
+    ///
+    /// The total timeout also bounds resolving the address, which `check`
+    /// leaves unbounded. Without STARTTLS, the I/O timeout bounds the whole
+    /// TLS handshake rather than each single read or write as with `check`.
+    pub async fn check_async<A: ToSocketAddrs>(&self,
+                                               addr: A,
+                                               server_name: Option<&str>)
+                                               -> Result<SslExpiration> {
+        match with_timeout(self.timeout, self.check_async_inner(addr, server_name)).await {
+            Some(result) => result,
+            None => Err(Error::Timeout("before the check completed")),
+        }
+    }
+
+    async fn check_async_inner<A: ToSocketAddrs>(&self,
+                                                 addr: A,
+                                                 server_name: Option<&str>)
+                                                 -> Result<SslExpiration> {
+        let addrs: Vec<SocketAddr> =
+            net::lookup_host(addr).await.map_err(Error::Resolve)?.collect();
+        if self.starttls.is_some() {
+            let checker = self.clone();
+            let server_name = server_name.map(|n| n.to_owned());
+            return task::spawn_blocking(move || checker.check(&addrs[..], server_name.as_deref()))
+                .await
+                .map_err(|e| Error::Io(e.into()))?;
+        }
+
+        let (ssl, verify_error) = self.ssl(server_name)?;
+        let domain = server_name.unwrap_or("-");
+        let started = Instant::now();
+        let stream = self.connect_async(&addrs).await?;
+        let peer_addr = stream.peer_addr()?;
+        debug!("{}: connected to {} in {:?}", domain, peer_addr, started.elapsed());
+
+        let handshake_started = Instant::now();
+        let mut stream = SslStream::new(ssl, stream)?;
+        match with_timeout(self.io_timeout, Pin::new(&mut stream).connect()).await {
+            Some(result) => result.map_err(Error::from_ssl)?,
+            None => return Err(Error::Timeout("during the TLS handshake")),
+        }
+        debug!("{}: TLS handshake with {} completed in {:?} using {}",
+               domain,
+               peer_addr,
+               handshake_started.elapsed(),
+               stream.ssl().version_str());
+        let expiration = self.expiration(stream.ssl(), server_name, peer_addr, &verify_error)?;
+        debug!("{}: checked {} in {:?}", domain, peer_addr, started.elapsed());
+        Ok(expiration)
+    }
+
+    /// Connects to the first reachable address of `addrs`.
+    async fn connect_async(&self, addrs: &[SocketAddr]) -> Result<TcpStream> {
+        let mut last_error = None;
+        for &addr in addrs {
+            let result = match with_timeout(self.connect_timeout, TcpStream::connect(addr)).await {
+                Some(result) => result,
+                None => Err(io::Error::new(io::ErrorKind::TimedOut, "connection timed out")),
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    debug!("connecting to {} failed: {}", addr, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) => Err(Error::from_connect(e)),
+            None => Err(no_addresses()),
+        }
+    }
+}
+
+/// Awaits `future`, returning `None` if it takes longer than `timeout`.
+async fn with_timeout<F: Future>(timeout: Option<Duration>, future: F) -> Option<F::Output> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+    use tokio::runtime::{Builder, Runtime};
+    use crate::test_util;
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_all().build().unwrap()
+    }
+
+    #[test]
+    fn test_check_async() {
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let (addr, sni) = test_util::serve(cert, key);
+        let expiration = runtime()
+            .block_on(SslExpiration::from_addr_with_server_name_async(addr, "example.test"))
+            .unwrap();
+        assert_eq!(expiration.days(), 30);
+        assert_eq!(expiration.hostname_matches(), Some(true));
+        assert_eq!(sni.recv().unwrap(), Some("example.test".to_owned()));
+
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        test_util::assert_err!(runtime().block_on(SslExpiration::from_addr_async(addr)),
+                               Error::ConnectionRefused(_));
+    }
+
+    #[test]
+    fn test_timeout_async() {
+        // Accepts connections but never answers the handshake.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _stream = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(5));
+        });
+        let checker = Checker::new().timeout(Duration::from_millis(200));
+        test_util::assert_err!(runtime().block_on(checker.check_async(addr, None)),
+                               Error::Timeout(_));
+    }
+}
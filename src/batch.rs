@@ -0,0 +1,288 @@
+//! Concurrent checking of many targets.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::panic;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::thread;
+
+use crate::error::Result;
+use crate::{AddressChecks, Checker, SslExpiration};
+
+/// A host to check, with an optional port.
+///
+/// Without a port, the HTTPS port or the well known port of the STARTTLS
+/// protocol of the checker is used.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Target {
+    host: String,
+    port: Option<u16>,
+}
+
+impl Target {
+    pub fn new(host: &str, port: Option<u16>) -> Target {
+        Target {
+            host: host.to_owned(),
+            port,
+        }
+    }
+
+    /// Domain name or IP address of the target.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    /// Parses `HOST[:PORT]`, with IPv6 addresses in brackets if a port
+    /// follows.
+    fn from_str(s: &str) -> ::std::result::Result<Target, String> {
+        if s.is_empty() {
+            return Err("empty target".to_owned());
+        }
+        if let Some(host) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            return Ok(Target::new(host, None));
+        }
+        if let Some((host, port)) = s.rsplit_once(':') {
+            if !host.contains(':') || host.starts_with('[') {
+                let port = port.parse().map_err(|_| format!("invalid port: {}", port))?;
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                return Ok(Target::new(host, Some(port)));
+            }
+        }
+        Ok(Target::new(s, None))
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.port {
+            Some(port) if self.host.contains(':') => write!(f, "[{}]:{}", self.host, port),
+            Some(port) => write!(f, "{}:{}", self.host, port),
+            None => write!(f, "{}", self.host),
+        }
+    }
+}
+
+/// Checks many targets concurrently.
+///
+/// ```rust,no_run
+/// use ssl_expiration::{Batch, Checker};
+///
+/// let targets = ["example.com".parse().unwrap(), "example.org:8443".parse().unwrap()];
+/// let results = Batch::new(Checker::new()).workers(4).per_host(1).check(&targets);
+/// for (target, result) in targets.iter().zip(results) {
+///     println!("{}: {:?}", target, result.map(|e| e.days()));
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Batch {
+    checker: Checker,
+    workers: usize,
+    per_host: Option<usize>,
+}
+
+/// Targets not checked yet and number of running checks per host.
+struct Queue {
+    pending: VecDeque<usize>,
+    running: HashMap<String, usize>,
+}
+
+/// Counts a check of `host` as finished when dropped, even if it panicked, so
+/// that workers waiting for the host are woken up.
+struct Running<'a> {
+    queue: &'a Mutex<Queue>,
+    done: &'a Condvar,
+    host: String,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = queue.running.get_mut(&self.host) {
+            *count -= 1;
+        }
+        self.done.notify_all();
+    }
+}
+
+impl Batch {
+    /// Creates a batch checking with `checker` on 8 workers, without a limit
+    /// per host.
+    pub fn new(checker: Checker) -> Batch {
+        Batch {
+            checker,
+            workers: 8,
+            per_host: None,
+        }
+    }
+
+    /// Runs up to `workers` checks at the same time.
+    pub fn workers(mut self, workers: usize) -> Batch {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Runs up to `limit` checks of the same host at the same time.
+    pub fn per_host(mut self, limit: usize) -> Batch {
+        self.per_host = Some(limit.max(1));
+        self
+    }
+
+    /// Checks every target, returning the results in the order of `targets`.
+    pub fn check(&self, targets: &[Target]) -> Vec<Result<SslExpiration>> {
+        self.run(targets, |checker, target| checker.check_target(target))
+    }
+
+    /// Checks every address of every target, returning the results in the
+    /// order of `targets`.
+    pub fn check_each_address(&self, targets: &[Target]) -> Vec<Result<AddressChecks>> {
+        self.run(targets, |checker, target| {
+            checker.check_each_address((target.host(), checker.port(target)),
+                                       Some(target.host()))
+        })
+    }
+
+    fn run<F, R>(&self, targets: &[Target], check: F) -> Vec<R>
+        where F: Fn(&Checker, &Target) -> R + Sync,
+              R: Send
+    {
+        let queue = Mutex::new(Queue {
+            pending: (0..targets.len()).collect(),
+            running: HashMap::new(),
+        });
+        let done = Condvar::new();
+
+        let mut results: Vec<Option<R>> = targets.iter().map(|_| None).collect();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.workers.min(targets.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = vec![];
+                        while let Some(i) = self.next(&queue, &done, targets) {
+                            let _running = Running {
+                                queue: &queue,
+                                done: &done,
+                                host: host_key(&targets[i]),
+                            };
+                            results.push((i, check(&self.checker, &targets[i])));
+                        }
+                        results
+                    })
+                })
+                .collect();
+            for worker in workers {
+                for (i, result) in worker.join().unwrap_or_else(|e| panic::resume_unwind(e)) {
+                    results[i] = Some(result);
+                }
+            }
+        });
+        results.into_iter().map(|r| r.expect("every target is checked")).collect()
+    }
+
+    /// Takes the first pending target whose host is below the limit, waiting
+    /// for running checks to finish if there is none.
+    fn next(&self, queue: &Mutex<Queue>, done: &Condvar, targets: &[Target]) -> Option<usize> {
+        let mut queue = queue.lock().unwrap();
+        loop {
+            if queue.pending.is_empty() {
+                return None;
+            }
+            let position = {
+                let running = &queue.running;
+                queue.pending.iter().position(|&i| {
+                    let count = running.get(&host_key(&targets[i])).cloned().unwrap_or(0);
+                    self.per_host.map_or(true, |limit| count < limit)
+                })
+            };
+            if let Some(position) = position {
+                let i = queue.pending.remove(position).expect("position is in the queue");
+                *queue.running.entry(host_key(&targets[i])).or_insert(0) += 1;
+                return Some(i);
+            }
+            queue = done.wait(queue).unwrap();
+        }
+    }
+}
+
+/// Host name the politeness limit applies to.
+fn host_key(target: &Target) -> String {
+    target.host().trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::error::Error;
+    use crate::test_util;
+
+    #[test]
+    fn test_target_from_str() {
+        assert_eq!("example.com".parse(), Ok(Target::new("example.com", None)));
+        assert_eq!("example.com:8443".parse(), Ok(Target::new("example.com", Some(8443))));
+        assert_eq!("[::1]:443".parse(), Ok(Target::new("::1", Some(443))));
+        assert_eq!("::1".parse(), Ok(Target::new("::1", None)));
+        assert_eq!("[::1]".parse(), Ok(Target::new("::1", None)));
+        assert!("example.com:https".parse::<Target>().is_err());
+        assert_eq!(Target::new("::1", Some(443)).to_string(), "[::1]:443");
+    }
+
+    #[test]
+    fn test_batch() {
+        let mut targets = vec![];
+        for days in [30, 20, 10] {
+            let (cert, key) = test_util::self_signed("127.0.0.1", days);
+            let (addr, _) = test_util::serve(cert, key);
+            targets.push(Target::new("127.0.0.1", Some(addr.port())));
+        }
+        let refused = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        targets.insert(1, Target::new("127.0.0.1", Some(refused.port())));
+
+        let results = Batch::new(Checker::new()).workers(3).per_host(2).check(&targets);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap().days(), 30);
+        test_util::assert_err!(results[1], Error::ConnectionRefused(_));
+        assert_eq!(results[2].as_ref().unwrap().days(), 20);
+        assert_eq!(results[3].as_ref().unwrap().days(), 10);
+    }
+
+    #[test]
+    fn test_per_host_limit() {
+        let targets: Vec<Target> =
+            (1..13).map(|port| Target::new("example.test", Some(port))).collect();
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        Batch::new(Checker::new()).workers(8).per_host(2).run(&targets, |_, _| {
+            peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            running.fetch_sub(1, Ordering::SeqCst);
+        });
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_panicking_check() {
+        let targets: Vec<Target> =
+            (1..5).map(|port| Target::new("example.test", Some(port))).collect();
+        let batch = Batch::new(Checker::new()).workers(2).per_host(1);
+        let result = panic::catch_unwind(|| {
+            batch.run(&targets, |_, target| {
+                if target.port() == Some(1) {
+                    panic!("check failed");
+                }
+                target.port()
+            })
+        });
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! Connections bounded by the total timeout of a check.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use crate::min_timeout;
+
+/// Socket whose reads and writes give up after the I/O timeout, or once the
+/// deadline of the check passed.
+///
+/// The socket timeouts are set again before every operation, so a server
+/// sending one byte at a time cannot keep a check running past its deadline.
+#[derive(Debug)]
+pub(crate) struct DeadlineStream {
+    stream: TcpStream,
+    io_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl DeadlineStream {
+    pub fn new(stream: TcpStream,
+               io_timeout: Option<Duration>,
+               deadline: Option<Instant>)
+               -> DeadlineStream {
+        DeadlineStream {
+            stream,
+            io_timeout,
+            deadline,
+        }
+    }
+
+    /// Another handle to the same connection, bound by the same deadline.
+    pub fn try_clone(&self) -> io::Result<DeadlineStream> {
+        Ok(DeadlineStream::new(self.stream.try_clone()?, self.io_timeout, self.deadline))
+    }
+
+    /// Timeout of the next operation.
+    fn timeout(&self) -> io::Result<Option<Duration>> {
+        let remaining = match self.deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline passed"));
+                }
+                Some(deadline - now)
+            }
+            None => None,
+        };
+        Ok(min_timeout(self.io_timeout, remaining))
+    }
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(self.timeout()?)?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for DeadlineStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(self.timeout()?)?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
@@ -0,0 +1,257 @@
+//! Errors that can occur while checking a certificate.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+use openssl::error::ErrorStack;
+use openssl::ssl::{self, HandshakeError};
+
+pub type Result<T> = result::Result<T, Error>;
+
+// Values of OpenSSL's ERR_LIB_SSL, SSL_AD_REASON_OFFSET and SSL_R_* macros,
+// which are not exported by openssl-sys.
+const ERR_LIB_SSL: i32 = 20;
+const SSL_AD_REASON_OFFSET: i32 = 1000;
+const SSL_R_NO_CIPHERS_AVAILABLE: i32 = 181;
+const SSL_R_NO_PROTOCOLS_AVAILABLE: i32 = 191;
+const SSL_R_NO_SHARED_CIPHER: i32 = 193;
+const SSL_R_PACKET_LENGTH_TOO_LONG: i32 = 198;
+const SSL_R_UNKNOWN_PROTOCOL: i32 = 252;
+const SSL_R_UNSUPPORTED_PROTOCOL: i32 = 258;
+const SSL_R_WRONG_VERSION_NUMBER: i32 = 267;
+const SSL_R_VERSION_TOO_LOW: i32 = 396;
+
+/// TLS alert sent by servers that do not support the offered versions.
+const ALERT_PROTOCOL_VERSION: u8 = 70;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The host name could not be resolved.
+    Resolve(io::Error),
+    /// The server refused the TCP connection.
+    ConnectionRefused(io::Error),
+    /// The TCP connection could not be established for another reason.
+    Connect(io::Error),
+    /// The given stage of the check took too long.
+    Timeout(&'static str),
+    /// The server aborted the TLS handshake with an alert.
+    TlsAlert {
+        /// Alert description code, as listed in RFC 8446 section 6.
+        alert: u8,
+        error: ssl::Error,
+    },
+    /// Client and server have no TLS version or cipher suite in common, or
+    /// the server does not speak TLS at all.
+    ProtocolMismatch(ssl::Error),
+    /// The TLS handshake failed for another reason.
+    Handshake(ssl::Error),
+    /// The server does not offer to upgrade the connection to TLS.
+    StartTlsNotSupported,
+    /// Upgrading the connection to TLS failed, e.g. because of an unexpected
+    /// reply of the server.
+    StartTls(String),
+    /// The server did not send a certificate.
+    CertificateNotFound,
+    /// A key store file is malformed, its password is wrong or it is encrypted
+    /// with an unsupported algorithm.
+    KeyStore(String),
+    /// An OpenSSL call failed.
+    OpenSsl(ErrorStack),
+    /// Any other I/O error.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Classifies a failed handshake.
+    pub(crate) fn from_handshake<S>(e: HandshakeError<S>) -> Error {
+        let e = match e {
+            HandshakeError::SetupFailure(e) => return Error::OpenSsl(e),
+            HandshakeError::Failure(s) | HandshakeError::WouldBlock(s) => s.into_error(),
+        };
+        Error::from_ssl(e)
+    }
+
+    /// Classifies the error of a failed handshake.
+    pub(crate) fn from_ssl(e: ssl::Error) -> Error {
+        if e.io_error().is_some_and(is_timeout) {
+            return Error::Timeout("during the TLS handshake");
+        }
+
+        let reasons = e.ssl_error()
+            .map_or(vec![], |stack| {
+                stack.errors()
+                    .iter()
+                    .filter(|e| e.library_code() == ERR_LIB_SSL)
+                    .map(|e| e.reason_code())
+                    .collect()
+            });
+        for reason in reasons {
+            match reason {
+                SSL_R_NO_CIPHERS_AVAILABLE |
+                SSL_R_NO_PROTOCOLS_AVAILABLE |
+                SSL_R_NO_SHARED_CIPHER |
+                SSL_R_PACKET_LENGTH_TOO_LONG |
+                SSL_R_UNKNOWN_PROTOCOL |
+                SSL_R_UNSUPPORTED_PROTOCOL |
+                SSL_R_WRONG_VERSION_NUMBER |
+                SSL_R_VERSION_TOO_LOW => return Error::ProtocolMismatch(e),
+                r if (SSL_AD_REASON_OFFSET..SSL_AD_REASON_OFFSET + 256).contains(&r) => {
+                    let alert = (r - SSL_AD_REASON_OFFSET) as u8;
+                    if alert == ALERT_PROTOCOL_VERSION {
+                        return Error::ProtocolMismatch(e);
+                    }
+                    return Error::TlsAlert { alert, error: e };
+                }
+                _ => {}
+            }
+        }
+        Error::Handshake(e)
+    }
+
+    /// Classifies a failed TCP connection.
+    pub(crate) fn from_connect(e: io::Error) -> Error {
+        if is_timeout(&e) {
+            Error::Timeout("connecting")
+        } else if e.kind() == io::ErrorKind::ConnectionRefused {
+            Error::ConnectionRefused(e)
+        } else {
+            Error::Connect(e)
+        }
+    }
+
+    /// Returns true if this error is a timeout.
+    pub fn is_timeout(&self) -> bool {
+        matches!(*self, Error::Timeout(_))
+    }
+}
+
+pub(crate) fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Name of a TLS alert description code.
+fn alert_name(alert: u8) -> &'static str {
+    match alert {
+        0 => "close_notify",
+        10 => "unexpected_message",
+        20 => "bad_record_mac",
+        22 => "record_overflow",
+        40 => "handshake_failure",
+        42 => "bad_certificate",
+        43 => "unsupported_certificate",
+        44 => "certificate_revoked",
+        45 => "certificate_expired",
+        46 => "certificate_unknown",
+        47 => "illegal_parameter",
+        48 => "unknown_ca",
+        49 => "access_denied",
+        50 => "decode_error",
+        51 => "decrypt_error",
+        70 => "protocol_version",
+        71 => "insufficient_security",
+        80 => "internal_error",
+        86 => "inappropriate_fallback",
+        90 => "user_canceled",
+        109 => "missing_extension",
+        110 => "unsupported_extension",
+        112 => "unrecognized_name",
+        113 => "bad_certificate_status_response",
+        115 => "unknown_psk_identity",
+        116 => "certificate_required",
+        120 => "no_application_protocol",
+        _ => "unknown",
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Resolve(ref e) => write!(f, "Could not resolve address: {}", e),
+            Error::ConnectionRefused(_) => write!(f, "Connection refused"),
+            Error::Connect(ref e) => write!(f, "Could not connect: {}", e),
+            Error::Timeout(stage) => write!(f, "Timed out {}", stage),
+            Error::TlsAlert { alert, .. } => {
+                write!(f, "Server sent TLS alert {} ({})", alert, alert_name(alert))
+            }
+            Error::ProtocolMismatch(ref e) => write!(f, "No common TLS protocol: {}", e),
+            Error::Handshake(ref e) => write!(f, "TLS handshake failed: {}", e),
+            Error::StartTlsNotSupported => write!(f, "Server does not support STARTTLS"),
+            Error::StartTls(ref e) => write!(f, "STARTTLS negotiation failed: {}", e),
+            Error::CertificateNotFound => write!(f, "Certificate not found"),
+            Error::KeyStore(ref e) => write!(f, "Invalid key store: {}", e),
+            Error::OpenSsl(ref e) => write!(f, "OpenSSL error: {}", e),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Resolve(ref e) |
+            Error::ConnectionRefused(ref e) |
+            Error::Connect(ref e) |
+            Error::Io(ref e) => Some(e),
+            Error::TlsAlert { ref error, .. } => Some(error),
+            Error::ProtocolMismatch(ref e) | Error::Handshake(ref e) => Some(e),
+            Error::OpenSsl(ref e) => Some(e),
+            Error::Timeout(_) |
+            Error::StartTlsNotSupported |
+            Error::StartTls(_) |
+            Error::CertificateNotFound |
+            Error::KeyStore(_) => None,
+        }
+    }
+}
+
+impl From<ErrorStack> for Error {
+    fn from(e: ErrorStack) -> Error {
+        Error::OpenSsl(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::io::Write;
+    use std::thread;
+    use crate::test_util;
+    use crate::Checker;
+
+    #[test]
+    fn test_connection_refused() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        test_util::assert_err!(Checker::new().check(addr, None), Error::ConnectionRefused(_));
+    }
+
+    #[test]
+    fn test_protocol_mismatch() {
+        // A plain text server, as if TLS was attempted on an SMTP port.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(b"220 mail.example.test ESMTP ready\r\n");
+            thread::sleep(::std::time::Duration::from_millis(500));
+        });
+        test_util::assert_err!(Checker::new().check(addr, None), Error::ProtocolMismatch(_));
+    }
+
+    #[test]
+    fn test_tls_alert() {
+        let addr = test_util::serve_unrecognized_name(test_util::self_signed("example.test", 30));
+        let result = Checker::new().check(addr, Some("other.example.test"));
+        test_util::assert_err!(result, Error::TlsAlert { alert: 112, .. });
+        assert_eq!(result.err().unwrap().to_string(),
+                   "Server sent TLS alert 112 (unrecognized_name)");
+    }
+}
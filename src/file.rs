@@ -0,0 +1,190 @@
+//! Certificates read from files instead of servers.
+
+use std::fs;
+use std::path::Path;
+
+use openssl::error::ErrorStack;
+use openssl::pkcs12::Pkcs12;
+use openssl::x509::X509;
+
+use crate::error::{Error, Result};
+use crate::{time, CertificateInfo, SslExpiration};
+
+impl SslExpiration {
+    /// Creates new SslExpiration from PEM encoded certificates.
+    ///
+    /// Every certificate of a bundle is available from `chain`, the first one
+    /// is treated as the leaf certificate.
+    pub fn from_pem(pem: &[u8]) -> Result<SslExpiration> {
+        from_certificates(X509::stack_from_pem(pem)?)
+    }
+
+    /// Creates new SslExpiration from a DER encoded certificate.
+    pub fn from_der(der: &[u8]) -> Result<SslExpiration> {
+        from_certificates(vec![X509::from_der(der)?])
+    }
+
+    /// Creates new SslExpiration from a PEM or DER certificate file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<SslExpiration> {
+        let contents = fs::read(path)?;
+        if is_pem(&contents) {
+            SslExpiration::from_pem(&contents)
+        } else {
+            SslExpiration::from_der(&contents)
+        }
+    }
+
+    /// Creates new SslExpiration from a DER encoded PKCS#12 archive, as found
+    /// in `.p12` and `.pfx` files, decrypted with `password`.
+    ///
+    /// The certificate of the private key comes first in `chain`, followed
+    /// by every CA certificate of the archive.
+    pub fn from_pkcs12(der: &[u8], password: &str) -> Result<SslExpiration> {
+        let pkcs12 = Pkcs12::from_der(der)
+            .map_err(|_| Error::KeyStore("not a PKCS#12 archive".to_owned()))?;
+        let parsed = pkcs12.parse2(password).map_err(pkcs12_error)?;
+        let mut certs: Vec<X509> = parsed.cert.into_iter().collect();
+        if let Some(ca) = parsed.ca {
+            certs.extend(ca);
+        }
+        from_certificates(certs)
+    }
+
+    /// Creates new SslExpiration from a PKCS#12 file, decrypted with
+    /// `password`.
+    pub fn from_pkcs12_file<P: AsRef<Path>>(path: P, password: &str) -> Result<SslExpiration> {
+        SslExpiration::from_pkcs12(&fs::read(path)?, password)
+    }
+}
+
+/// Returns true if `contents` looks like PEM rather than DER.
+fn is_pem(contents: &[u8]) -> bool {
+    contents.windows(11).any(|w| w == b"-----BEGIN ")
+}
+
+/// Classifies an archive that could not be decrypted.
+///
+/// Legacy algorithms like RC2-40 are only provided by OpenSSL 3 when its
+/// legacy provider is enabled.
+fn pkcs12_error(e: ErrorStack) -> Error {
+    let has_reason = |reason| e.errors().iter().any(|e| e.reason() == Some(reason));
+    let reason = if has_reason("mac verify failure") {
+        "password incorrect or key store tampered with".to_owned()
+    } else if has_reason("unsupported") {
+        "legacy encryption like RC2-40 is not supported by OpenSSL, re-export the archive with \
+         AES or enable the OpenSSL legacy provider"
+            .to_owned()
+    } else {
+        e.to_string()
+    };
+    Error::KeyStore(reason)
+}
+
+pub(crate) fn from_certificates(certs: Vec<X509>) -> Result<SslExpiration> {
+    if certs.is_empty() {
+        return Err(Error::CertificateNotFound);
+    }
+    let now = time::now();
+    Ok(SslExpiration {
+        server_name: None,
+        peer_addr: None,
+        chain: certs.iter().map(|c| CertificateInfo::new(c, now)).collect::<Result<_>>()?,
+        verify_result: None,
+        hostname_matches: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::stack::Stack;
+    use crate::test_util;
+
+    #[test]
+    fn test_from_pem() {
+        let root = test_util::certificate("Test Root", 0, 3650, true, None);
+        let leaf = test_util::certificate("example.test", 0, 30, false, Some(&root));
+        let mut pem = leaf.0.to_pem().unwrap();
+        pem.extend_from_slice(&root.0.to_pem().unwrap());
+
+        let expiration = SslExpiration::from_pem(&pem).unwrap();
+        assert_eq!(expiration.days(), 30);
+        assert_eq!(expiration.chain().len(), 2);
+        assert_eq!(expiration.chain()[1].subject(), "CN=Test Root");
+        assert_eq!(expiration.peer_addr(), None);
+
+        test_util::assert_err!(SslExpiration::from_pem(b""), Error::CertificateNotFound);
+    }
+
+    #[test]
+    fn test_from_pkcs12() {
+        let root = test_util::certificate("Test Root", 0, 3650, true, None);
+        let intermediate = test_util::certificate("Test Intermediate", 0, 365, true, Some(&root));
+        let leaf = test_util::certificate("example.test", 0, 30, false, Some(&intermediate));
+        let mut ca = Stack::new().unwrap();
+        ca.push(intermediate.0).unwrap();
+        ca.push(root.0).unwrap();
+        let der = Pkcs12::builder()
+            .name("example.test")
+            .pkey(&leaf.1)
+            .cert(&leaf.0)
+            .ca(ca)
+            .build2("secret")
+            .unwrap()
+            .to_der()
+            .unwrap();
+
+        let path = test_util::temp_file("keystore.p12", &der);
+        let expiration = SslExpiration::from_pkcs12_file(&path, "secret").unwrap();
+        assert_eq!(expiration.days(), 30);
+        let subjects: Vec<&str> = expiration.chain().iter().map(|c| c.subject()).collect();
+        assert_eq!(subjects.len(), 3);
+        assert_eq!(subjects[0], "CN=example.test");
+        assert!(subjects.contains(&"CN=Test Intermediate"));
+        assert!(subjects.contains(&"CN=Test Root"));
+
+        test_util::assert_err!(SslExpiration::from_pkcs12(&der, "wrong"),
+                               Error::KeyStore(e) if e.contains("password incorrect"));
+        test_util::assert_err!(SslExpiration::from_pkcs12(&der[1..], "secret"),
+                               Error::KeyStore(_));
+    }
+
+    /// Certificate of `legacy.example.test` encrypted with RC2-40 by
+    /// `openssl pkcs12 -export -legacy -nokeys`, password `secret`.
+    const LEGACY_PKCS12: &str = "\
+        MIICbAIBAzCCAjIGCSqGSIb3DQEHAaCCAiMEggIfMIICGzCCAhcGCSqGSIb3DQEHBqCCAggw\
+        ggIEAgEAMIIB/QYJKoZIhvcNAQcBMBwGCiqGSIb3DQEMAQYwDgQI8ibA0fteZAICAggAgIIB\
+        0COJcf5NdA9oL85SZj2wDZ3A03UhpAPDsd+khR+vEB7ZhZXW86NM36QO3UHkRkpoXedQZ6Xy\
+        1HNXL68SzKGjGJEPVLULKwjY5MqrB6LyZuL0LF4IjyYDoE2VjwEDrP7KKKArPlk9H38TpcZz\
+        kuSmS6/TPec3aiBmjQlPjSQzn1sDwK9xAlVpsUJ8mIM1SDy9lIz1WV0r8T+k7JVBeLiZMjv/\
+        OkrrGdkp7/QVUA+X33itC/T5OxL9RwtRjqxfjNYFyJv72T4Eu5KljsPhPgT+tONJ7n2a93Gw\
+        mwDv7UoVKyaezIjnv4e2NgvylzLLsP28gt+bNmYj8YH5tbyfYGDEzTcfDv5umbo3k2CJiIfg\
+        pTBYaa5ssUy3lmAahIDX2pUJBhsy2/sLLqD18texO8JF8oz3ctjzP24vbCnHiuv123ROgeFd\
+        L1C+iZH6p02aS4hNT+4HnJM+qMCpIMhwyAH7XLwMgI+zL9vdy7WMs6WN85OuADp4axcU2b5X\
+        NQiDaueSAvsbHgsKmGyIoNmsVERm0JJJ1XDgw2sI5r2OqyhyjXvqwZj1NfMLaAg0iE1Ra6Qv\
+        XeHZ7mbroicL0NUnbjIv6jEXf7wj4xtum9NPWt46NyAJMDEwITAJBgUrDgMCGgUABBQfHzPq\
+        J0aZT/ognmpNcVHFcXiKpAQIEmxn7nyDoKcCAggA";
+
+    #[test]
+    fn test_from_pkcs12_legacy() {
+        let der = openssl::base64::decode_block(LEGACY_PKCS12).unwrap();
+        // Succeeds where the OpenSSL legacy provider is enabled.
+        match SslExpiration::from_pkcs12(&der, "secret") {
+            Ok(expiration) => {
+                assert_eq!(expiration.certificate().subject(), "CN=legacy.example.test")
+            }
+            Err(Error::KeyStore(_)) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_from_file() {
+        let (cert, _) = test_util::self_signed("example.test", 20);
+        let der = test_util::temp_file("cert.der", &cert.to_der().unwrap());
+        assert_eq!(SslExpiration::from_file(&der).unwrap().days(), 20);
+        let pem = test_util::temp_file("cert.pem", &cert.to_pem().unwrap());
+        assert_eq!(SslExpiration::from_file(&pem).unwrap().days(), 20);
+        assert!(SslExpiration::from_file(pem.with_extension("missing")).is_err());
+    }
+}
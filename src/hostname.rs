@@ -0,0 +1,98 @@
+//! Matching of the requested name against a certificate, following RFC 6125.
+
+use std::net::IpAddr;
+
+use openssl::nid::Nid;
+use openssl::x509::X509Ref;
+
+/// Returns true if the certificate is valid for `name`, a DNS name or an IP
+/// address.
+///
+/// IP addresses only match IP address subject alternative names. DNS names
+/// match DNS subject alternative names, or the common names of the subject if
+/// the certificate has no DNS subject alternative name.
+pub fn matches(cert: &X509Ref, name: &str) -> bool {
+    let alt_names = cert.subject_alt_names();
+
+    if let Ok(ip) = name.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        return alt_names.is_some_and(|names| {
+            names.iter().filter_map(|n| n.ipaddress()).any(|addr| ip_matches(addr, &ip))
+        });
+    }
+
+    let dns_names = alt_names.map_or(vec![], |names| {
+        names.iter().filter_map(|n| n.dnsname().map(|n| n.to_owned())).collect()
+    });
+    if !dns_names.is_empty() {
+        return dns_names.iter().any(|pattern| dns_matches(pattern, name));
+    }
+
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|entry| entry.data().to_string().ok())
+        .any(|cn| dns_matches(&cn, name))
+}
+
+fn ip_matches(addr: &[u8], ip: &IpAddr) -> bool {
+    match *ip {
+        IpAddr::V4(ip) => addr == &ip.octets()[..],
+        IpAddr::V6(ip) => addr == &ip.octets()[..],
+    }
+}
+
+/// Matches a DNS name against a pattern from the certificate.
+///
+/// A wildcard is only honoured as the whole left-most label of a pattern with
+/// at least two more labels, and matches exactly one label.
+fn dns_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    if pattern.is_empty() || name.is_empty() {
+        return false;
+    }
+
+    if !pattern.starts_with("*.") {
+        return !pattern.contains('*') && pattern == name;
+    }
+
+    let suffix = &pattern[2..];
+    if suffix.contains('*') || suffix.split('.').count() < 2 {
+        return false;
+    }
+    match name.find('.') {
+        Some(dot) => dot > 0 && &name[dot + 1..] == suffix,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn test_dns_matches() {
+        assert!(dns_matches("example.com", "example.com"));
+        assert!(dns_matches("Example.COM", "example.com."));
+        assert!(!dns_matches("example.com", "www.example.com"));
+        assert!(dns_matches("*.example.com", "www.example.com"));
+        assert!(!dns_matches("*.example.com", "example.com"));
+        assert!(!dns_matches("*.example.com", "a.b.example.com"));
+        assert!(!dns_matches("*.com", "example.com"));
+        assert!(!dns_matches("www.*.com", "www.example.com"));
+        assert!(!dns_matches("w*.example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn test_matches() {
+        let (cert, _) = test_util::self_signed("example.test", 30);
+        assert!(matches(&cert, "example.test"));
+        assert!(!matches(&cert, "other.test"));
+        assert!(!matches(&cert, "127.0.0.1"));
+
+        let (cert, _) = test_util::self_signed("127.0.0.1", 30);
+        assert!(matches(&cert, "127.0.0.1"));
+        assert!(!matches(&cert, "127.0.0.2"));
+        assert!(!matches(&cert, "::1"));
+    }
+}
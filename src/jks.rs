@@ -0,0 +1,342 @@
+//! Java KeyStore (JKS and JCEKS) files.
+
+use std::fs;
+use std::path::Path;
+
+use openssl::sha::Sha1;
+use openssl::x509::X509;
+
+use crate::error::{Error, Result};
+use crate::file::from_certificates;
+use crate::SslExpiration;
+
+const JKS_MAGIC: u32 = 0xfeed_feed;
+const JCEKS_MAGIC: u32 = 0xcece_cece;
+
+// Entry tags.
+const PRIVATE_KEY: u32 = 1;
+const TRUSTED_CERTIFICATE: u32 = 2;
+const SECRET_KEY: u32 = 3;
+
+/// Salt of the integrity digest at the end of the file.
+const DIGEST_SALT: &[u8] = b"Mighty Aphrodite";
+const DIGEST_LEN: usize = 20;
+
+/// Kind of a Java KeyStore entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyStoreEntryKind {
+    /// A private key with its certificate chain.
+    PrivateKey,
+    /// A trusted certificate, as found in truststores.
+    TrustedCertificate,
+}
+
+/// A certificate bearing entry of a Java KeyStore.
+pub struct KeyStoreEntry {
+    alias: String,
+    kind: KeyStoreEntryKind,
+    expiration: SslExpiration,
+}
+
+impl KeyStoreEntry {
+    pub fn alias(&self) -> &str {
+        &self.alias
+    }
+
+    pub fn kind(&self) -> KeyStoreEntryKind {
+        self.kind
+    }
+
+    /// Certificates of the entry: the chain of a private key, starting with
+    /// its own certificate, or the trusted certificate.
+    pub fn expiration(&self) -> &SslExpiration {
+        &self.expiration
+    }
+}
+
+/// Contents of a JKS or JCEKS file.
+pub struct JavaKeyStore {
+    entries: Vec<KeyStoreEntry>,
+    truncated: bool,
+}
+
+impl JavaKeyStore {
+    /// Parses a JKS or JCEKS key store.
+    ///
+    /// The integrity of the key store is checked if `password` is given.
+    /// Private keys are not decrypted, so reading a truststore does not need
+    /// a password.
+    ///
+    /// Reading stops at the first secret key entry of a JCEKS file, see
+    /// `is_truncated`.
+    pub fn from_bytes(data: &[u8], password: Option<&str>) -> Result<JavaKeyStore> {
+        if data.len() < DIGEST_LEN {
+            return Err(invalid("file too short"));
+        }
+        let (contents, digest) = data.split_at(data.len() - DIGEST_LEN);
+        let mut reader = Reader { data: contents };
+        let magic = reader.u32()?;
+        if magic != JKS_MAGIC && magic != JCEKS_MAGIC {
+            return Err(invalid("not a JKS or JCEKS file"));
+        }
+        let version = reader.u32()?;
+        if version != 1 && version != 2 {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        if let Some(password) = password {
+            if integrity_digest(contents, password)[..] != *digest {
+                return Err(invalid("password incorrect or key store tampered with"));
+            }
+        }
+
+        let count = reader.u32()?;
+        let mut entries = vec![];
+        let mut truncated = false;
+        for _ in 0..count {
+            let tag = reader.u32()?;
+            let alias = reader.utf()?;
+            // Creation date in milliseconds since the Unix epoch.
+            reader.take(8)?;
+            let (kind, certs) = match tag {
+                PRIVATE_KEY => {
+                    let key_len = reader.u32()? as usize;
+                    reader.take(key_len)?;
+                    let chain_len = reader.u32()?;
+                    let chain = (0..chain_len)
+                        .map(|_| reader.certificate(version))
+                        .collect::<Result<_>>()?;
+                    (KeyStoreEntryKind::PrivateKey, chain)
+                }
+                TRUSTED_CERTIFICATE => {
+                    (KeyStoreEntryKind::TrustedCertificate, vec![reader.certificate(version)?])
+                }
+                // Secret keys are serialized Java objects without a length,
+                // the entries after one cannot be found.
+                SECRET_KEY => {
+                    debug!("stopped reading key store at secret key entry {}", alias);
+                    truncated = true;
+                    break;
+                }
+                tag => return Err(invalid(&format!("unknown entry tag {}", tag))),
+            };
+            if certs.is_empty() {
+                debug!("skipped key store entry {} without certificates", alias);
+                continue;
+            }
+            let expiration = from_certificates(certs)?;
+            entries.push(KeyStoreEntry { alias, kind, expiration });
+        }
+        Ok(JavaKeyStore { entries, truncated })
+    }
+
+    /// Reads a JKS or JCEKS file, see `from_bytes`.
+    pub fn from_file<P: AsRef<Path>>(path: P, password: Option<&str>) -> Result<JavaKeyStore> {
+        JavaKeyStore::from_bytes(&fs::read(path)?, password)
+    }
+
+    /// Private key and trusted certificate entries, in file order.
+    ///
+    /// Private keys without a certificate chain are left out.
+    pub fn entries(&self) -> &[KeyStoreEntry] {
+        &self.entries
+    }
+
+    /// Returns true if reading stopped at a secret key entry, so that the
+    /// entries after it are missing from `entries`.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::KeyStore(reason.to_owned())
+}
+
+/// SHA-1 of the password as UTF-16BE, the salt and the key store contents.
+fn integrity_digest(contents: &[u8], password: &str) -> [u8; DIGEST_LEN] {
+    let mut sha1 = Sha1::new();
+    for c in password.encode_utf16() {
+        sha1.update(&c.to_be_bytes());
+    }
+    sha1.update(DIGEST_SALT);
+    sha1.update(contents);
+    sha1.finish()
+}
+
+/// Big endian reader of Java `DataOutputStream` values.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(invalid("unexpected end of file"));
+        }
+        let (value, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(value)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Reads a length prefixed modified UTF-8 string.
+    fn utf(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    /// Reads a certificate, preceded by its type since version 2.
+    fn certificate(&mut self, version: u32) -> Result<X509> {
+        if version == 2 {
+            let kind = self.utf()?;
+            if kind != "X.509" {
+                return Err(invalid(&format!("unsupported certificate type {}", kind)));
+            }
+        }
+        let len = self.u32()? as usize;
+        Ok(X509::from_der(self.take(len)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn utf(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn certificate(out: &mut Vec<u8>, cert: &X509) {
+        let der = cert.to_der().unwrap();
+        utf(out, "X.509");
+        out.extend_from_slice(&(der.len() as u32).to_be_bytes());
+        out.extend_from_slice(&der);
+    }
+
+    fn header(out: &mut Vec<u8>, magic: u32, count: u32) {
+        out.extend_from_slice(&magic.to_be_bytes());
+        out.extend_from_slice(&2u32.to_be_bytes());
+        out.extend_from_slice(&count.to_be_bytes());
+    }
+
+    fn entry(out: &mut Vec<u8>, tag: u32, alias: &str) {
+        out.extend_from_slice(&tag.to_be_bytes());
+        utf(out, alias);
+        out.extend_from_slice(&0u64.to_be_bytes());
+    }
+
+    fn private_key(out: &mut Vec<u8>, alias: &str, chain: &[&X509]) {
+        entry(out, PRIVATE_KEY, alias);
+        out.extend_from_slice(&4u32.to_be_bytes());
+        out.extend_from_slice(b"\x00key");
+        out.extend_from_slice(&(chain.len() as u32).to_be_bytes());
+        for cert in chain {
+            certificate(out, cert);
+        }
+    }
+
+    fn trusted_certificate(out: &mut Vec<u8>, alias: &str, cert: &X509) {
+        entry(out, TRUSTED_CERTIFICATE, alias);
+        certificate(out, cert);
+    }
+
+    fn with_digest(mut out: Vec<u8>, password: &str) -> Vec<u8> {
+        let digest = integrity_digest(&out, password);
+        out.extend_from_slice(&digest);
+        out
+    }
+
+    /// Writes a version 2 key store with a private key entry for `leaf`
+    /// issued by `root`, and a trusted certificate entry for `root`.
+    fn key_store(magic: u32, leaf: &X509, root: &X509, password: &str) -> Vec<u8> {
+        let mut out = vec![];
+        header(&mut out, magic, 2);
+        private_key(&mut out, "tomcat", &[leaf, root]);
+        trusted_certificate(&mut out, "root", root);
+        with_digest(out, password)
+    }
+
+    /// Writes a JCEKS key store with a secret key entry between a private key
+    /// entry for `leaf` and a trusted certificate entry for `root`.
+    fn key_store_with_secret_key(leaf: &X509, root: &X509, password: &str) -> Vec<u8> {
+        let mut out = vec![];
+        header(&mut out, JCEKS_MAGIC, 3);
+        private_key(&mut out, "tomcat", &[leaf, root]);
+        entry(&mut out, SECRET_KEY, "aes");
+        // Start of a serialized javax.crypto.SealedObject.
+        out.extend_from_slice(b"\xac\xed\x00\x05sr\x00\x19javax.crypto.SealedObject");
+        trusted_certificate(&mut out, "root", root);
+        with_digest(out, password)
+    }
+
+    #[test]
+    fn test_java_key_store() {
+        let root = test_util::certificate("Test Root", -3650, -1, true, None);
+        let leaf = test_util::certificate("example.test", 0, 30, false, Some(&root));
+        let data = key_store(JKS_MAGIC, &leaf.0, &root.0, "changeit");
+
+        let path = test_util::temp_file("keystore.jks", &data);
+        let store = JavaKeyStore::from_file(&path, Some("changeit")).unwrap();
+        let entries = store.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].alias(), "tomcat");
+        assert_eq!(entries[0].kind(), KeyStoreEntryKind::PrivateKey);
+        assert_eq!(entries[0].expiration().days(), 30);
+        assert_eq!(entries[0].expiration().chain().len(), 2);
+        assert_eq!(entries[1].alias(), "root");
+        assert_eq!(entries[1].kind(), KeyStoreEntryKind::TrustedCertificate);
+        assert!(entries[1].expiration().is_expired());
+
+        assert_eq!(JavaKeyStore::from_bytes(&data, None).unwrap().entries().len(), 2);
+        test_util::assert_err!(JavaKeyStore::from_bytes(&data, Some("wrong")), Error::KeyStore(_));
+        test_util::assert_err!(JavaKeyStore::from_bytes(&data[..data.len() / 2], None),
+                               Error::KeyStore(_));
+
+        let data = key_store(JCEKS_MAGIC, &leaf.0, &root.0, "changeit");
+        let store = JavaKeyStore::from_bytes(&data, Some("changeit")).unwrap();
+        assert_eq!(store.entries().len(), 2);
+        assert!(!store.is_truncated());
+
+        let data = leaf.0.to_der().unwrap();
+        test_util::assert_err!(JavaKeyStore::from_bytes(&data, Some("changeit")),
+                               Error::KeyStore(e) if e == "not a JKS or JCEKS file");
+    }
+
+    #[test]
+    fn test_private_key_without_chain() {
+        let root = test_util::certificate("Test Root", 0, 3650, true, None);
+        let mut out = vec![];
+        header(&mut out, JKS_MAGIC, 2);
+        private_key(&mut out, "orphan", &[]);
+        trusted_certificate(&mut out, "root", &root.0);
+        let data = with_digest(out, "changeit");
+
+        let store = JavaKeyStore::from_bytes(&data, Some("changeit")).unwrap();
+        assert_eq!(store.entries().len(), 1);
+        assert_eq!(store.entries()[0].alias(), "root");
+    }
+
+    #[test]
+    fn test_secret_key() {
+        let root = test_util::certificate("Test Root", 0, 3650, true, None);
+        let leaf = test_util::certificate("example.test", 0, 30, false, Some(&root));
+        let data = key_store_with_secret_key(&leaf.0, &root.0, "changeit");
+
+        let store = JavaKeyStore::from_bytes(&data, Some("changeit")).unwrap();
+        assert!(store.is_truncated());
+        assert_eq!(store.entries().len(), 1);
+        assert_eq!(store.entries()[0].alias(), "tomcat");
+        assert_eq!(store.entries()[0].expiration().days(), 30);
+    }
+}
@@ -17,107 +17,941 @@ extern crate foreign_types_shared;
 extern crate openssl;
 extern crate openssl_sys;
 #[macro_use]
-extern crate error_chain;
+extern crate log;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "async")]
+extern crate tokio_openssl;
 
-use std::net::{TcpStream, ToSocketAddrs};
-use std::error::Error;
-use openssl::ssl::{Ssl, SslContext, SslMethod, SslVerifyMode};
-use openssl::asn1::Asn1Time;
-use error::Result;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use openssl::ssl::{Ssl, SslContext, SslMethod, SslRef, SslVerifyMode};
+use openssl::hash::MessageDigest;
+use openssl::pkey::Id;
+use openssl::x509::{X509NameRef, X509Ref, X509VerifyResult};
+use crate::deadline::DeadlineStream;
+use crate::error::{Error, Result};
+use crate::starttls::Transport;
+
+pub use batch::{Batch, Target};
+pub use jks::{JavaKeyStore, KeyStoreEntry, KeyStoreEntryKind};
+pub use starttls::StartTls;
+pub use time::{SignedDuration, Validity};
+pub use verify::{TrustAnchor, VerifyResult};
+
+#[cfg(feature = "async")]
+mod async_check;
+mod batch;
+mod deadline;
+pub mod error;
+mod file;
+mod hostname;
+mod jks;
+mod starttls;
+mod tds;
+mod time;
+mod verify;
 
 pub struct SslExpiration {
-    secs: i32,
-    alt_names: Vec<String>
+    server_name: Option<String>,
+    peer_addr: Option<SocketAddr>,
+    chain: Vec<CertificateInfo>,
+    verify_result: Option<VerifyResult>,
+    hostname_matches: Option<bool>,
+}
+
+/// First verification error of a handshake and the depth it occurred at.
+type VerifyError = Arc<Mutex<Option<(X509VerifyResult, u32)>>>;
+
+/// Results of checking every address a host name resolves to.
+pub struct AddressChecks {
+    results: Vec<(SocketAddr, Result<SslExpiration>)>,
+}
+
+/// Details of a single certificate of the served chain.
+#[derive(Clone, Debug)]
+pub struct CertificateInfo {
+    subject: String,
+    issuer: String,
+    serial: String,
+    not_before: i64,
+    not_after: i64,
+    checked_at: i64,
+    alt_names: Vec<String>,
+    sha1_fingerprint: String,
+    sha256_fingerprint: String,
+    key_type: String,
+    key_bits: u32,
+    signature_algorithm: String,
+}
+
+/// Checks SSL certificates with custom options.
+///
+/// ```rust,no_run
+/// use ssl_expiration::Checker;
+///
+/// let expiration = Checker::new().verify(true).check_domain("google.com").unwrap();
+/// assert!(expiration.verify_result().unwrap().is_trusted());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Checker {
+    verify: bool,
+    ca_files: Vec<PathBuf>,
+    ca_dirs: Vec<PathBuf>,
+    connect_timeout: Option<Duration>,
+    io_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    starttls: Option<StartTls>,
 }
 
 
 impl SslExpiration {
     /// Creates new SslExpiration from domain name.
     ///
-    /// This function will use HTTPS port (443) to check SSL certificate.
+    /// This function will use HTTPS port (443) to check SSL certificate and
+    /// will send the domain name with SNI.
     pub fn from_domain_name(domain: &str) -> Result<SslExpiration> {
-        SslExpiration::from_addr(format!("{}:443", domain))
+        SslExpiration::from_addr_with_server_name(format!("{}:443", domain), domain)
     }
 
     /// Creates new SslExpiration from SocketAddr.
+    ///
+    /// No server name is sent, so a server hosting several domains will
+    /// answer with its default certificate.
     pub fn from_addr<A: ToSocketAddrs>(addr: A) -> Result<SslExpiration> {
-        let context = {
-            let mut context = SslContext::builder(SslMethod::tls())?;
-            context.set_verify(SslVerifyMode::empty());
-            context.build()
-        };
-        let connector = Ssl::new(&context)?;
-        let stream = TcpStream::connect(addr)?;
-        let stream = connector.connect(stream)
-            .map_err(|e| error::ErrorKind::HandshakeError(e.description().to_owned()))?;
-        let cert = stream.ssl()
-            .peer_certificate()
-            .ok_or("Certificate not found")?;
-
-        let mut alt_names = vec![];
-        if let Some(names) = cert.subject_alt_names() {
-            alt_names = names.iter().filter_map(|n| n.dnsname()).map(|n| n.to_string()).collect();
-            for name in &alt_names {
-                println!("Alt: {}", name);//.dnsname().unwrap());
-            }
-        }
-        let now = Asn1Time::days_from_now(0)?;
-        let after = cert.not_after();
-        let before = cert.not_before();
-        let from_now = now.diff(cert.not_after())?;
-        println!("not before: {:?}", before);
-        println!("not after: {:?}", after);
-        let verify = cert.verify(&cert.public_key().unwrap());
-        println!("Verify: {:?}", verify);
-        Ok(SslExpiration { secs: from_now.days * 24 * 60 * 60 + from_now.secs, alt_names })
+        Checker::new().check(addr, None)
+    }
+
+    /// Creates new SslExpiration from SocketAddr, asking for the certificate
+    /// of `server_name` with SNI.
+    ///
+    /// SNI is not sent if `server_name` is an IP address.
+    pub fn from_addr_with_server_name<A: ToSocketAddrs>(addr: A,
+                                                        server_name: &str)
+                                                        -> Result<SslExpiration> {
+        Checker::new().check(addr, Some(server_name))
+    }
+
+    /// Server name the certificate was requested for, if any.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Address of the server that was checked.
+    ///
+    /// Returns `None` for certificates read from files.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Time left until SSL certificate expires, at the time of the check.
+    ///
+    /// This function will return a negative duration if SSL certificate is
+    /// already expired.
+    pub fn remaining(&self) -> SignedDuration {
+        self.certificate().remaining()
     }
 
     /// How many seconds until SSL certificate expires.
     ///
     /// This function will return minus if SSL certificate is already expired.
-    pub fn secs(&self) -> i32 {
-        self.secs
+    pub fn secs(&self) -> i64 {
+        self.remaining().as_secs()
     }
 
     /// How many days until SSL certificate expires
     ///
     /// This function will return minus if SSL certificate is already expired.
-    pub fn days(&self) -> i32 {
-        self.secs / 60 / 60 / 24
+    pub fn days(&self) -> i64 {
+        self.remaining().as_days()
     }
 
     /// Returns true if SSL certificate is expired
     pub fn is_expired(&self) -> bool {
-        self.secs < 0
+        self.remaining().is_negative()
+    }
+
+    /// Validity state of SSL certificate at the time of the check.
+    ///
+    /// The certificate is `Expiring` if it expires within `expiring_within`.
+    pub fn validity(&self, expiring_within: Duration) -> Validity {
+        self.certificate().validity(expiring_within)
+    }
+
+    /// Details of the leaf certificate.
+    pub fn certificate(&self) -> &CertificateInfo {
+        &self.chain[0]
+    }
+
+    /// Certificates sent by the server, starting with the leaf certificate.
+    pub fn chain(&self) -> &[CertificateInfo] {
+        &self.chain
+    }
+
+    /// Outcome of validating the chain against the trust store.
+    ///
+    /// Returns `None` if validation was not requested with `Checker::verify`.
+    pub fn verify_result(&self) -> Option<&VerifyResult> {
+        self.verify_result.as_ref()
+    }
+
+    /// Returns true if the certificate is valid for the requested server name,
+    /// matching its subject alternative names as described in RFC 6125.
+    ///
+    /// Returns `None` if no server name was requested.
+    pub fn hostname_matches(&self) -> Option<bool> {
+        self.hostname_matches
+    }
+
+    /// The certificate of the chain that expires first.
+    ///
+    /// Its `not_after` is the time at which the whole chain expires.
+    pub fn chain_expiration(&self) -> &CertificateInfo {
+        self.chain.iter().min_by_key(|c| c.not_after).expect("chain contains the leaf certificate")
     }
 }
 
+impl AddressChecks {
+    /// Outcome of the check of each address, in resolution order.
+    pub fn results(&self) -> &[(SocketAddr, Result<SslExpiration>)] {
+        &self.results
+    }
+
+    /// Returns true if every address that was checked successfully served
+    /// the same leaf certificate.
+    ///
+    /// Addresses whose check failed are not compared, their errors are found
+    /// in `results`.
+    pub fn is_consistent(&self) -> bool {
+        let mut fingerprints = self.results
+            .iter()
+            .filter_map(|(_, result)| result.as_ref().ok())
+            .map(|e| e.certificate().sha256_fingerprint());
+        match fingerprints.next() {
+            Some(first) => fingerprints.all(|f| f == first),
+            None => true,
+        }
+    }
+}
+
+impl IntoIterator for AddressChecks {
+    type Item = (SocketAddr, Result<SslExpiration>);
+    type IntoIter = ::std::vec::IntoIter<(SocketAddr, Result<SslExpiration>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.results.into_iter()
+    }
+}
+
+impl Checker {
+    /// Creates a checker with default options.
+    pub fn new() -> Checker {
+        Checker::default()
+    }
+
+    /// Validates the served chain against the system trust store.
+    ///
+    /// Expiration is still reported when validation fails, the outcome is
+    /// available from `SslExpiration::verify_result`.
+    pub fn verify(mut self, verify: bool) -> Checker {
+        self.verify = verify;
+        self
+    }
+
+    /// Also trusts the PEM CA certificates of `path` when validating.
+    ///
+    /// This enables validation and can be called several times.
+    pub fn ca_file<P: AsRef<Path>>(mut self, path: P) -> Checker {
+        self.verify = true;
+        self.ca_files.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Also trusts the CA certificates of the hashed directory `path`, as
+    /// prepared by `openssl rehash`, when validating.
+    ///
+    /// This enables validation and can be called several times.
+    pub fn ca_dir<P: AsRef<Path>>(mut self, path: P) -> Checker {
+        self.verify = true;
+        self.ca_dirs.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Gives up connecting to an address after `timeout`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Checker {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Gives up when a single read or write on the connection takes longer
+    /// than `timeout`.
+    ///
+    /// Without STARTTLS, `check_async` applies `timeout` to the whole TLS
+    /// handshake instead.
+    pub fn io_timeout(mut self, timeout: Duration) -> Checker {
+        self.io_timeout = Some(timeout);
+        self
+    }
+
+    /// Gives up when the whole check, from connecting to receiving the
+    /// certificate, takes longer than `timeout`.
+    ///
+    /// Resolving the address is not bounded by `timeout`, except with
+    /// `check_async`.
+    pub fn timeout(mut self, timeout: Duration) -> Checker {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Upgrades the connection to TLS with `protocol` before the handshake.
+    pub fn starttls(mut self, protocol: StartTls) -> Checker {
+        self.starttls = Some(protocol);
+        self
+    }
+
+    /// Checks the certificate of `domain`, sending the domain name with SNI.
+    ///
+    /// This function will use HTTPS port (443), or the well known port of the
+    /// protocol given with `starttls`.
+    pub fn check_domain(&self, domain: &str) -> Result<SslExpiration> {
+        self.check_target(&Target::new(domain, None))
+    }
+
+    /// Checks the certificate of `target`, sending its host name with SNI.
+    pub fn check_target(&self, target: &Target) -> Result<SslExpiration> {
+        self.check((target.host(), self.port(target)), Some(target.host()))
+    }
+
+    /// Port of `target`, defaulting to the port of the protocol.
+    fn port(&self, target: &Target) -> u16 {
+        target.port().unwrap_or_else(|| self.starttls.map_or(443, |p| p.default_port()))
+    }
+
+    /// Checks every address `addr` resolves to separately, sending the same
+    /// server name to each of them.
+    ///
+    /// Unlike `check`, which stops at the first reachable address, this finds
+    /// nodes of a pool serving a different certificate. Timeouts apply to the
+    /// check of each address.
+    pub fn check_each_address<A: ToSocketAddrs>(&self,
+                                                addr: A,
+                                                server_name: Option<&str>)
+                                                -> Result<AddressChecks> {
+        let mut addrs: Vec<SocketAddr> = vec![];
+        for addr in addr.to_socket_addrs().map_err(Error::Resolve)? {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        if addrs.is_empty() {
+            return Err(no_addresses());
+        }
+        let results = addrs.into_iter()
+            .map(|addr| {
+                let result = self.check(addr, server_name);
+                if let Err(ref e) = result {
+                    debug!("{}: checking {} failed: {}", server_name.unwrap_or("-"), addr, e);
+                }
+                (addr, result)
+            })
+            .collect();
+        Ok(AddressChecks { results })
+    }
+
+    /// Checks the certificate served on `addr`, asking for the certificate of
+    /// `server_name` with SNI when it is given and not an IP address.
+    pub fn check<A: ToSocketAddrs>(&self,
+                                   addr: A,
+                                   server_name: Option<&str>)
+                                   -> Result<SslExpiration> {
+        let (connector, verify_error) = self.ssl(server_name)?;
+
+        let domain = server_name.unwrap_or("-");
+        let started = Instant::now();
+        // A timeout too large for an `Instant`, like `Duration::MAX`, is no limit.
+        let deadline = self.timeout.and_then(|timeout| started.checked_add(timeout));
+        let stream = self.connect(addr, deadline)?;
+        let peer_addr = stream.peer_addr()?;
+        debug!("{}: connected to {} in {:?}", domain, peer_addr, started.elapsed());
+
+        let stream = DeadlineStream::new(stream, self.io_timeout, deadline);
+        let stream = match self.starttls {
+            Some(protocol) => {
+                let stream = protocol.negotiate(stream, server_name)?;
+                debug!("{}: upgraded connection to {} with {} STARTTLS",
+                       domain,
+                       peer_addr,
+                       protocol);
+                stream
+            }
+            None => Transport::Plain(stream),
+        };
 
+        let handshake_started = Instant::now();
+        let stream = connector.connect(stream).map_err(Error::from_handshake)?;
+        debug!("{}: TLS handshake with {} completed in {:?} using {}",
+               domain,
+               peer_addr,
+               handshake_started.elapsed(),
+               stream.ssl().version_str());
+        let expiration = self.expiration(stream.ssl(), server_name, peer_addr, &verify_error)?;
+        debug!("{}: checked {} in {:?}", domain, peer_addr, started.elapsed());
+        Ok(expiration)
+    }
 
-pub mod error {
-    use std::io;
-    use openssl;
+    /// Prepares the client side of the handshake, returning it with the slot
+    /// the first verification error is recorded in.
+    fn ssl(&self, server_name: Option<&str>) -> Result<(Ssl, VerifyError)> {
+        let context = {
+            let mut context = SslContext::builder(SslMethod::tls())?;
+            if self.verify {
+                context.set_default_verify_paths()?;
+                for file in &self.ca_files {
+                    context.load_verify_locations(Some(file), None)?;
+                }
+                for dir in &self.ca_dirs {
+                    context.load_verify_locations(None, Some(dir))?;
+                }
+            }
+            context.build()
+        };
+        let mut connector = Ssl::new(&context)?;
+        if let Some(name) = server_name {
+            if name.parse::<IpAddr>().is_err() {
+                connector.set_hostname(name)?;
+            }
+        }
+
+        // Verification failures must not abort the handshake, remember the
+        // first one and carry on.
+        let verify_error = Arc::new(Mutex::new(None));
+        {
+            let verify_error = verify_error.clone();
+            connector.set_verify_callback(SslVerifyMode::PEER, move |ok, context| {
+                let mut verify_error = verify_error.lock().unwrap();
+                if !ok && verify_error.is_none() {
+                    *verify_error = Some((context.error(), context.error_depth()));
+                }
+                true
+            });
+        }
+        Ok((connector, verify_error))
+    }
+
+    /// Collects the certificates of a completed handshake.
+    fn expiration(&self,
+                  ssl: &SslRef,
+                  server_name: Option<&str>,
+                  peer_addr: SocketAddr,
+                  verify_error: &VerifyError)
+                  -> Result<SslExpiration> {
+        let domain = server_name.unwrap_or("-");
+        let cert = ssl.peer_certificate().ok_or(Error::CertificateNotFound)?;
+
+        let now = time::now();
+        debug!("{}: certificate of {} valid from {} until {}",
+               domain,
+               peer_addr,
+               cert.not_before(),
+               cert.not_after());
+
+        // The client side chain starts with the leaf certificate.
+        let chain = match ssl.peer_cert_chain() {
+            Some(chain) if !chain.is_empty() => {
+                chain.iter().map(|c| CertificateInfo::new(c, now)).collect::<Result<_>>()?
+            }
+            _ => vec![CertificateInfo::new(&cert, now)?],
+        };
+
+        let expiration = SslExpiration {
+            server_name: server_name.map(|n| n.to_owned()),
+            peer_addr: Some(peer_addr),
+            chain,
+            hostname_matches: server_name.map(|name| hostname::matches(&cert, name)),
+            verify_result: if self.verify {
+                Some(match verify_error.lock().unwrap().take() {
+                    Some((error, depth)) => VerifyResult::from_error(error, depth),
+                    None => VerifyResult::Trusted(self.trust_anchor(ssl)?),
+                })
+            } else {
+                None
+            },
+        };
+        if let Some(result) = expiration.verify_result() {
+            debug!("{}: certificate chain of {} is {}", domain, peer_addr, result);
+        }
+        Ok(expiration)
+    }
+
+    /// Connects to the first reachable address of `addr`.
+    fn connect<A: ToSocketAddrs>(&self, addr: A, deadline: Option<Instant>) -> Result<TcpStream> {
+        let mut last_error = None;
+        for addr in addr.to_socket_addrs().map_err(Error::Resolve)? {
+            let result = match min_timeout(self.connect_timeout, remaining(deadline)?) {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    debug!("connecting to {} failed: {}", addr, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) => Err(Error::from_connect(e)),
+            None => Err(no_addresses()),
+        }
+    }
 
-    error_chain! {
-        foreign_links {
-            OpenSslErrorStack(openssl::error::ErrorStack);
-            IoError(io::Error);
+    /// Finds out whether a trusted chain is trusted by the custom anchors.
+    fn trust_anchor(&self, ssl: &SslRef) -> Result<TrustAnchor> {
+        if self.ca_files.is_empty() && self.ca_dirs.is_empty() {
+            return Ok(TrustAnchor::System);
+        }
+        let store = verify::custom_store(&self.ca_files, &self.ca_dirs)?;
+        match ssl.peer_cert_chain() {
+            Some(chain) if verify::is_trusted_by(&store, chain)? => Ok(TrustAnchor::Custom),
+            _ => Ok(TrustAnchor::System),
         }
-        errors {
-            HandshakeError(e: String) {
-                description("HandshakeError")
-                display("HandshakeError: {}", e)
+    }
+}
+
+impl CertificateInfo {
+    fn new(cert: &X509Ref, checked_at: i64) -> Result<CertificateInfo> {
+        let alt_names = cert.subject_alt_names().map_or(vec![], |names| {
+            names.iter()
+                .filter_map(|name| {
+                    name.dnsname()
+                        .map(|n| n.to_owned())
+                        .or_else(|| name.ipaddress().and_then(ip_to_string))
+                })
+                .collect()
+        });
+        let key = cert.public_key()?;
+        let key_type = match key.id() {
+            Id::RSA => "RSA",
+            Id::DSA => "DSA",
+            Id::EC => "EC",
+            Id::ED25519 => "Ed25519",
+            Id::ED448 => "Ed448",
+            _ => "unknown",
+        };
+        let signature_algorithm = cert.signature_algorithm().object().nid();
+        Ok(CertificateInfo {
+            subject: name_to_string(cert.subject_name()),
+            issuer: name_to_string(cert.issuer_name()),
+            serial: cert.serial_number().to_bn()?.to_hex_str()?.to_string(),
+            not_before: time::unix_secs(cert.not_before())?,
+            not_after: time::unix_secs(cert.not_after())?,
+            checked_at,
+            alt_names,
+            sha1_fingerprint: fingerprint(&cert.digest(MessageDigest::sha1())?),
+            sha256_fingerprint: fingerprint(&cert.digest(MessageDigest::sha256())?),
+            key_type: key_type.to_owned(),
+            key_bits: key.bits(),
+            signature_algorithm: signature_algorithm.long_name().unwrap_or("unknown").to_owned(),
+        })
+    }
+
+    /// Subject of the certificate, e.g. `CN=example.com, O=Example`.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Issuer of the certificate.
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    /// Serial number of the certificate in hexadecimal.
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// DNS names and IP addresses of the subject alternative names.
+    pub fn alt_names(&self) -> &[String] {
+        &self.alt_names
+    }
+
+    /// SHA-1 fingerprint of the certificate, e.g. `AB:CD:...`.
+    pub fn sha1_fingerprint(&self) -> &str {
+        &self.sha1_fingerprint
+    }
+
+    /// SHA-256 fingerprint of the certificate.
+    pub fn sha256_fingerprint(&self) -> &str {
+        &self.sha256_fingerprint
+    }
+
+    /// Type of the public key: `RSA`, `DSA`, `EC`, `Ed25519` or `Ed448`.
+    pub fn key_type(&self) -> &str {
+        &self.key_type
+    }
+
+    /// Size of the public key in bits.
+    pub fn key_bits(&self) -> u32 {
+        self.key_bits
+    }
+
+    /// Signature algorithm, e.g. `sha256WithRSAEncryption`.
+    pub fn signature_algorithm(&self) -> &str {
+        &self.signature_algorithm
+    }
+
+    /// Start of the validity period.
+    pub fn not_before(&self) -> SystemTime {
+        time::system_time(self.not_before)
+    }
+
+    /// End of the validity period.
+    pub fn not_after(&self) -> SystemTime {
+        time::system_time(self.not_after)
+    }
+
+    /// Start of the validity period in seconds since the Unix epoch.
+    pub fn not_before_unix(&self) -> i64 {
+        self.not_before
+    }
+
+    /// End of the validity period in seconds since the Unix epoch.
+    pub fn not_after_unix(&self) -> i64 {
+        self.not_after
+    }
+
+    /// Time left until this certificate expires, at the time of the check.
+    pub fn remaining(&self) -> SignedDuration {
+        SignedDuration::from_secs(self.not_after - self.checked_at)
+    }
+
+    /// How many seconds until this certificate expires.
+    ///
+    /// This function will return minus if the certificate is already expired.
+    pub fn secs(&self) -> i64 {
+        self.remaining().as_secs()
+    }
+
+    /// How many days until this certificate expires.
+    pub fn days(&self) -> i64 {
+        self.remaining().as_days()
+    }
+
+    /// Returns true if this certificate is expired.
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_negative()
+    }
+
+    /// Validity state of this certificate at the time of the check.
+    ///
+    /// The certificate is `Expiring` if it expires within `expiring_within`.
+    pub fn validity(&self, expiring_within: Duration) -> Validity {
+        Validity::at(self.not_before, self.not_after, self.checked_at, expiring_within)
+    }
+}
+
+/// Time left until `deadline`, failing with a timeout once it passed.
+fn remaining(deadline: Option<Instant>) -> Result<Option<Duration>> {
+    match deadline {
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline {
+                Err(Error::Timeout("before the check completed"))
+            } else {
+                Ok(Some(deadline - now))
             }
         }
+        None => Ok(None),
+    }
+}
+
+/// Error for an address that resolved to nothing.
+fn no_addresses() -> Error {
+    Error::Resolve(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "could not resolve to any addresses"))
+}
+
+fn min_timeout(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a < b { a } else { b }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn fingerprint(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+fn ip_to_string(addr: &[u8]) -> Option<String> {
+    if addr.len() == 4 {
+        let mut octets = [0; 4];
+        octets.copy_from_slice(addr);
+        Some(Ipv4Addr::from(octets).to_string())
+    } else if addr.len() == 16 {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(addr);
+        Some(Ipv6Addr::from(octets).to_string())
+    } else {
+        None
     }
 }
 
+fn name_to_string(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            match entry.data().to_string() {
+                Ok(value) => format!("{}={}", key, value),
+                Err(_) => format!("{}=?", key),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+
+
+#[cfg(test)]
+mod test_util;
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util;
     #[test]
     fn test_ssl_expiration() {
         assert!(!SslExpiration::from_domain_name("google.com").unwrap().is_expired());
         assert!(SslExpiration::from_domain_name("expired.identrustssl.com").unwrap().is_expired());
     }
+
+    #[test]
+    fn test_server_name() {
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let (addr, sni) = test_util::serve(cert, key);
+        let expiration = SslExpiration::from_addr_with_server_name(addr, "example.test").unwrap();
+        assert_eq!(expiration.server_name(), Some("example.test"));
+        assert_eq!(sni.recv().unwrap(), Some("example.test".to_owned()));
+
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let (addr, sni) = test_util::serve(cert, key);
+        let expiration = SslExpiration::from_addr(addr).unwrap();
+        assert_eq!(expiration.server_name(), None);
+        assert_eq!(sni.recv().unwrap(), None);
+    }
+
+    #[test]
+    fn test_hostname_matches() {
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let (addr, _) = test_util::serve(cert, key);
+        let expiration = SslExpiration::from_addr_with_server_name(addr, "example.test").unwrap();
+        assert_eq!(expiration.hostname_matches(), Some(true));
+
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let (addr, _) = test_util::serve(cert, key);
+        let expiration = SslExpiration::from_addr_with_server_name(addr, "other.test").unwrap();
+        assert_eq!(expiration.hostname_matches(), Some(false));
+
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let (addr, _) = test_util::serve(cert, key);
+        assert_eq!(SslExpiration::from_addr(addr).unwrap().hostname_matches(), None);
+    }
+
+    #[test]
+    fn test_timeout() {
+        use std::net::TcpListener;
+        use std::thread;
+
+        // Accepts connections but never answers the handshake.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _streams = listener.incoming().take(2).collect::<Vec<_>>();
+            thread::sleep(Duration::from_secs(5));
+        });
+
+        let started = Instant::now();
+        let checker = Checker::new().io_timeout(Duration::from_millis(200));
+        test_util::assert_err!(checker.check(addr, None), Error::Timeout(_));
+        let checker = Checker::new().timeout(Duration::from_millis(200));
+        test_util::assert_err!(checker.check(addr, None), Error::Timeout(_));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_unlimited_timeout() {
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let (addr, _) = test_util::serve(cert, key);
+        let expiration = Checker::new()
+            .connect_timeout(Duration::MAX)
+            .io_timeout(Duration::MAX)
+            .timeout(Duration::MAX)
+            .check(addr, None)
+            .unwrap();
+        assert_eq!(expiration.days(), 30);
+    }
+
+    #[test]
+    fn test_timeout_slow_server() {
+        use std::io::Write;
+        use std::net::TcpListener;
+        use std::thread;
+
+        // Starts a handshake record, then sends one byte every 300ms.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(&[0x16, 0x03, 0x03, 0x40, 0x00]);
+            for _ in 0..20 {
+                thread::sleep(Duration::from_millis(300));
+                if stream.write_all(&[0]).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let started = Instant::now();
+        test_util::assert_err!(Checker::new().timeout(Duration::from_secs(1)).check(addr, None),
+                               Error::Timeout(_));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_chain() {
+        let root = test_util::certificate("Test Root", -10, 3650, true, None);
+        let intermediate = test_util::certificate("Test Intermediate", -10, 5, true, Some(&root));
+        let leaf = test_util::certificate("example.test", 0, 60, false, Some(&intermediate));
+        let (addr, _) = test_util::serve_chain(leaf, vec![intermediate.0.clone()]);
+
+        let expiration = SslExpiration::from_addr_with_server_name(addr, "example.test").unwrap();
+        let chain = expiration.chain();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[0].subject(), "CN=example.test");
+        assert_eq!(chain[0].issuer(), "CN=Test Intermediate");
+        assert_eq!(chain[0].days(), expiration.days());
+        assert_eq!(chain[1].subject(), "CN=Test Intermediate");
+        assert_eq!(chain[1].issuer(), "CN=Test Root");
+        assert_eq!(expiration.chain_expiration().subject(), "CN=Test Intermediate");
+        assert!(expiration.chain_expiration().days() <= 5);
+    }
+
+    #[test]
+    fn test_long_validity() {
+        let (cert, key) = test_util::self_signed("example.test", 100 * 365);
+        let (addr, _) = test_util::serve(cert, key);
+        let expiration = SslExpiration::from_addr(addr).unwrap();
+        assert!(expiration.secs() > i32::MAX as i64);
+        assert_eq!(expiration.days(), 100 * 365);
+        assert!(!expiration.is_expired());
+        let not_after = expiration.certificate().not_after();
+        assert!(not_after > SystemTime::now() + Duration::from_secs(99 * 365 * 86400));
+    }
+
+    #[test]
+    fn test_validity() {
+        let week = Duration::from_secs(7 * 86400);
+        let (cert, key) = test_util::certificate("example.test", 2, 60, false, None);
+        let (addr, _) = test_util::serve(cert, key);
+        let expiration = SslExpiration::from_addr(addr).unwrap();
+        assert!(!expiration.is_expired());
+        assert_eq!(expiration.validity(week), Validity::NotYetValid);
+
+        let (cert, key) = test_util::self_signed("example.test", 3);
+        let (addr, _) = test_util::serve(cert, key);
+        let expiration = SslExpiration::from_addr(addr).unwrap();
+        assert_eq!(expiration.validity(week), Validity::Expiring);
+        assert_eq!(expiration.validity(Duration::from_secs(86400)), Validity::Valid);
+    }
+
+    #[test]
+    fn test_certificate_info() {
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let sha256 = cert.digest(MessageDigest::sha256()).unwrap();
+        let serial = cert.serial_number().to_bn().unwrap().to_hex_str().unwrap().to_string();
+        let (addr, _) = test_util::serve(cert, key);
+        let expiration = SslExpiration::from_addr(addr).unwrap();
+        let info = expiration.certificate().clone();
+        assert_eq!(info.subject(), "CN=example.test");
+        assert_eq!(info.serial(), serial);
+        assert_eq!(info.alt_names(), &["example.test".to_owned()]);
+        assert_eq!(info.sha256_fingerprint().len(), 32 * 3 - 1);
+        assert!(info.sha256_fingerprint().starts_with(&format!("{:02X}:", sha256[0])));
+        assert_eq!(info.sha1_fingerprint().len(), 20 * 3 - 1);
+        assert_eq!(info.key_type(), "EC");
+        assert_eq!(info.key_bits(), 256);
+        assert_eq!(info.signature_algorithm(), "ecdsa-with-SHA256");
+    }
+
+    #[test]
+    fn test_verify_result() {
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let (addr, _) = test_util::serve(cert, key);
+        let expiration = Checker::new().check(addr, Some("example.test")).unwrap();
+        assert_eq!(expiration.verify_result(), None);
+
+        let (cert, key) = test_util::self_signed("example.test", 30);
+        let (addr, _) = test_util::serve(cert, key);
+        let expiration = Checker::new().verify(true).check(addr, Some("example.test")).unwrap();
+        assert_eq!(expiration.verify_result(), Some(&VerifyResult::SelfSigned));
+        assert_eq!(expiration.days(), 30);
+
+        let root = test_util::certificate("Test Root", 0, 3650, true, None);
+        let intermediate = test_util::certificate("Test Intermediate", 0, 365, true, Some(&root));
+        let leaf = test_util::certificate("example.test", 0, 60, false, Some(&intermediate));
+        let (addr, _) = test_util::serve_chain(leaf, vec![]);
+        let expiration = Checker::new().verify(true).check(addr, Some("example.test")).unwrap();
+        assert_eq!(expiration.verify_result(), Some(&VerifyResult::MissingIntermediate));
+
+        let leaf = test_util::certificate("example.test", 0, 60, false, Some(&intermediate));
+        let (addr, _) = test_util::serve_chain(leaf, vec![intermediate.0.clone()]);
+        let expiration = Checker::new().verify(true).check(addr, Some("example.test")).unwrap();
+        assert_eq!(expiration.verify_result(), Some(&VerifyResult::UntrustedRoot));
+    }
+
+    #[test]
+    fn test_ca_file() {
+        let root = test_util::certificate("Test Root", 0, 3650, true, None);
+        let leaf = test_util::certificate("example.test", 0, 60, false, Some(&root));
+        let ca_file = test_util::temp_file("ca.pem", &root.0.to_pem().unwrap());
+        let (addr, _) = test_util::serve(leaf.0, leaf.1);
+        let expiration = Checker::new()
+            .ca_file(&ca_file)
+            .check(addr, Some("example.test"))
+            .unwrap();
+        assert_eq!(expiration.verify_result(),
+                   Some(&VerifyResult::Trusted(TrustAnchor::Custom)));
+    }
+
+    #[test]
+    fn test_check_each_address() {
+        use std::net::TcpListener;
+
+        let identity = test_util::self_signed("pool.example.test", 30);
+        let (addr1, _) = test_util::serve(identity.0.clone(), identity.1.clone());
+        let (addr2, _) = test_util::serve(identity.0, identity.1);
+        let refused = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let checks = Checker::new()
+            .check_each_address(&[addr1, refused, addr2][..], Some("pool.example.test"))
+            .unwrap();
+        assert_eq!(checks.results().len(), 3);
+        assert!(checks.results()[1].1.is_err());
+        assert_eq!(checks.results()[2].1.as_ref().unwrap().peer_addr(), Some(addr2));
+        assert!(checks.is_consistent());
+
+        let (cert, key) = test_util::self_signed("pool.example.test", 30);
+        let (addr1, _) = test_util::serve(cert, key);
+        let (cert, key) = test_util::self_signed("pool.example.test", 5);
+        let (addr2, _) = test_util::serve(cert, key);
+        let checks = Checker::new()
+            .check_each_address(&[addr1, addr2][..], Some("pool.example.test"))
+            .unwrap();
+        assert!(!checks.is_consistent());
+        let days: Vec<i64> =
+            checks.into_iter().map(|(_, result)| result.unwrap().days()).collect();
+        assert_eq!(days, vec![30, 5]);
+    }
 }
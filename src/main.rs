@@ -1,34 +1,353 @@
 extern crate ssl_expiration;
+extern crate log;
 
-use std::io::{stderr, Write};
+use std::fs;
+use std::io::{self, stderr, IsTerminal, Write};
 use std::env;
-use std::process::exit;
+use std::process::{exit, Command, Stdio};
+use std::time::Duration;
 
-fn main() {
+use log::{Log, Metadata, Record, LevelFilter};
+use ssl_expiration::{Batch, Checker, JavaKeyStore, SslExpiration, StartTls, Target, Validity,
+                     VerifyResult};
+use ssl_expiration::error::{Error, Result};
+
+const USAGE: &str = "Usage: ssl-expiration [OPTIONS] DOMAIN[:PORT]...
+       ssl-expiration file PATH...
+       ssl-expiration [--password-env VAR | --password-file FILE] pkcs12 PATH...
+       ssl-expiration [--password-env VAR | --password-file FILE] jks PATH...
+
+Options:
+    -v, --verbose     Print connection and certificate details to stderr
+    --verify          Validate certificate chains against the system trust store
+    --ca-file FILE    Also trust the PEM CA certificates of FILE (implies --verify)
+    --ca-dir DIR      Also trust the CA certificates of the hashed DIR (implies --verify)
+    --connect-timeout SECS
+                      Give up connecting to a server after SECS seconds
+    --io-timeout SECS Give up when the server does not answer for SECS seconds
+    --timeout SECS    Give up checking a domain after SECS seconds
+    --all-addresses   Check every address a domain resolves to separately
+    --workers N       Check up to N domains at the same time (default: 8)
+    --per-host N      Check the same host at most N times at the same time
+    --password-env VAR
+                      Read the key store password from the environment variable VAR
+    --password-file FILE
+                      Read the key store password from the first line of FILE
+                      (otherwise it is prompted for, and echoed where the
+                      terminal cannot be controlled with stty, e.g. Windows)
+    --starttls PROTO  Upgrade the connection to TLS with PROTO: smtp, imap,
+                      pop3, postgres, mysql, ldap, ftp, xmpp, xmpp-server,
+                      nntp, tds (SQL Server) or rdp";
+
+/// Certificates expiring within this period are reported as expiring soon.
+const EXPIRING_WITHIN: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+fn usage() -> ! {
+    let _ = writeln!(stderr(), "{}", USAGE);
+    exit(2);
+}
+
+/// Writes log records of the library to stderr.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("ssl_expiration")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let _ = writeln!(stderr(), "[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn seconds(value: Option<String>) -> Duration {
+    match value.and_then(|v| v.parse::<f64>().ok()) {
+        Some(secs) if secs > 0.0 => Duration::from_millis((secs * 1000.0) as u64),
+        _ => usage(),
+    }
+}
+
+fn count(value: Option<String>) -> usize {
+    match value.and_then(|v| v.parse::<usize>().ok()) {
+        Some(count) if count > 0 => count,
+        _ => usage(),
+    }
+}
+
+/// Prints the outcome of the check of `domain`, returning false if there is
+/// something wrong with its certificate.
+fn report(domain: &str, expiration: &SslExpiration) -> bool {
+    let mut ok = report_validity(domain, expiration.validity(EXPIRING_WITHIN), expiration.days());
+    if expiration.hostname_matches() == Some(false) {
+        let _ = writeln!(stderr(), "{} SSL certificate does not match the domain name", domain);
+        ok = false;
+    }
+    match expiration.verify_result() {
+        Some(&VerifyResult::Trusted(_)) | None => {}
+        Some(result) => {
+            let _ = writeln!(stderr(), "{} SSL certificate is not trusted: {}", domain, result);
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// Prints the validity of a certificate of `label` with `days` left,
+/// returning false unless it is valid.
+fn report_validity(label: &str, validity: Validity, days: i64) -> bool {
+    match validity {
+        Validity::NotYetValid => {
+            let _ = writeln!(stderr(), "{} SSL certificate is not valid yet", label);
+            false
+        }
+        Validity::Expired => {
+            let _ = writeln!(stderr(), "{} SSL certificate expired {} days ago", label, -days);
+            false
+        }
+        Validity::Expiring => {
+            println!("{} SSL certificate will expire soon, in {} days", label, days);
+            false
+        }
+        Validity::Valid => {
+            println!("{} SSL certificate will expire in {} days", label, days);
+            true
+        }
+    }
+}
+
+/// Reports every certificate read from `paths` with `read`.
+fn check_files<F>(paths: &[String], read: F) -> i32
+    where F: Fn(&str) -> Result<SslExpiration>
+{
     let mut exit_code = 0;
-    for domain in env::args().skip(1) {
-        match ssl_expiration::SslExpiration::from_domain_name(&domain) {
+    for path in paths {
+        match read(path) {
             Ok(expiration) => {
-                let days = expiration.days();
-                if expiration.is_expired() {
-                    let _ = writeln!(stderr(),
-                                     "{} SSL certificate expired {} days ago",
-                                     domain,
-                                     !days);
+                if !report_chain(path, &expiration) {
                     exit_code = 1;
-                } else if expiration.days() <= 7 {
-                    println!("{} SSL certificate will expire soon, in {} days", domain, days);
+                }
+            }
+            Err(e) => {
+                report_error(path, &e);
+                exit_code = 1;
+            }
+        }
+    }
+    exit_code
+}
+
+/// Reports the certificates of every entry of the Java KeyStores at `paths`.
+fn check_key_stores(paths: &[String], password: Option<&str>) -> i32 {
+    let mut exit_code = 0;
+    for path in paths {
+        match JavaKeyStore::from_file(path, password) {
+            Ok(store) => {
+                for entry in store.entries() {
+                    let label = format!("{} {}", path, entry.alias());
+                    if !report_chain(&label, entry.expiration()) {
+                        exit_code = 1;
+                    }
+                }
+                if store.is_truncated() {
+                    let _ = writeln!(stderr(),
+                                     "{} entries after a secret key entry were not checked",
+                                     path);
                     exit_code = 1;
+                }
+            }
+            Err(e) => {
+                report_error(path, &e);
+                exit_code = 1;
+            }
+        }
+    }
+    exit_code
+}
+
+/// Prints the validity of every certificate of `expiration`, returning false
+/// unless they are all valid.
+fn report_chain(label: &str, expiration: &SslExpiration) -> bool {
+    let mut ok = true;
+    for cert in expiration.chain() {
+        let label = format!("{} ({})", label, cert.subject());
+        ok &= report_validity(&label, cert.validity(EXPIRING_WITHIN), cert.days());
+    }
+    ok
+}
+
+/// Where the password of PKCS#12 and Java KeyStore files comes from.
+enum Password {
+    Prompt,
+    Env(String),
+    File(String),
+}
+
+impl Password {
+    fn read(&self) -> io::Result<String> {
+        match *self {
+            Password::Env(ref var) => {
+                env::var(var).map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))
+            }
+            Password::File(ref path) => {
+                Ok(fs::read_to_string(path)?.lines().next().unwrap_or("").to_owned())
+            }
+            Password::Prompt => {
+                // Hide the password while it is typed, if stdin is a terminal.
+                let echo_off = Command::new("stty")
+                    .arg("-echo")
+                    .stderr(Stdio::null())
+                    .status()
+                    .is_ok_and(|s| s.success());
+                if echo_off || !io::stdin().is_terminal() {
+                    write!(stderr(), "Password: ")?;
                 } else {
-                    println!("{} SSL certificate will expire in {} days", domain, days);
+                    write!(stderr(), "Password (will be echoed): ")?;
+                }
+                let mut password = String::new();
+                let result = io::stdin().read_line(&mut password);
+                if echo_off {
+                    let _ = Command::new("stty").arg("echo").status();
+                    writeln!(stderr())?;
+                }
+                result?;
+                Ok(password.trim_end_matches(['\r', '\n']).to_owned())
+            }
+        }
+    }
+}
+
+fn read_password(source: &Password) -> String {
+    match source.read() {
+        Ok(password) => password,
+        Err(e) => {
+            let _ = writeln!(stderr(), "Could not read the password: {}", e);
+            exit(2);
+        }
+    }
+}
+
+fn report_error(domain: &str, e: &Error) {
+    let _ = writeln!(stderr(), "An error occured when checking {}: {}", domain, e);
+}
+
+fn main() {
+    let mut checker = Checker::new();
+    let mut all_addresses = false;
+    let mut workers = 8;
+    let mut per_host = None;
+    let mut password = None;
+    let mut domains = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-v" | "--verbose" => {
+                let _ = log::set_logger(&LOGGER);
+                log::set_max_level(LevelFilter::Debug);
+            }
+            "--verify" => checker = checker.verify(true),
+            "--ca-file" => checker = checker.ca_file(args.next().unwrap_or_else(|| usage())),
+            "--ca-dir" => checker = checker.ca_dir(args.next().unwrap_or_else(|| usage())),
+            "--connect-timeout" => checker = checker.connect_timeout(seconds(args.next())),
+            "--io-timeout" => checker = checker.io_timeout(seconds(args.next())),
+            "--timeout" => checker = checker.timeout(seconds(args.next())),
+            "--starttls" => {
+                match args.next().map(|p| p.parse::<StartTls>()) {
+                    Some(Ok(protocol)) => checker = checker.starttls(protocol),
+                    _ => usage(),
                 }
             }
+            "--all-addresses" => all_addresses = true,
+            "--workers" => workers = count(args.next()),
+            "--per-host" => per_host = Some(count(args.next())),
+            "--password-env" => {
+                password = Some(Password::Env(args.next().unwrap_or_else(|| usage())))
+            }
+            "--password-file" => {
+                password = Some(Password::File(args.next().unwrap_or_else(|| usage())))
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            _ if arg.starts_with("-") => usage(),
+            _ => domains.push(arg),
+        }
+    }
+
+    match domains.first().map(|d| d.as_str()) {
+        Some("file") => exit(check_files(&domains[1..], |path| SslExpiration::from_file(path))),
+        Some("pkcs12") => {
+            let password = read_password(&password.unwrap_or(Password::Prompt));
+            let read = |path: &str| SslExpiration::from_pkcs12_file(path, &password);
+            exit(check_files(&domains[1..], read));
+        }
+        Some("jks") => {
+            // Without a password the integrity of the key store is not checked.
+            let password = password.map(|p| read_password(&p));
+            exit(check_key_stores(&domains[1..], password.as_deref()));
+        }
+        _ => {}
+    }
+
+    let mut targets = vec![];
+    for domain in &domains {
+        match domain.parse::<Target>() {
+            Ok(target) => targets.push(target),
             Err(e) => {
-                let _ = writeln!(stderr(),
-                                 "An error occured when checking {}: {}",
-                                 domain,
-                                 e.description());
+                let _ = writeln!(stderr(), "Invalid target {}: {}", domain, e);
+                exit(2);
+            }
+        }
+    }
+
+    let mut batch = Batch::new(checker).workers(workers);
+    if let Some(limit) = per_host {
+        batch = batch.per_host(limit);
+    }
+
+    let mut exit_code = 0;
+    if !all_addresses {
+        for (domain, result) in domains.iter().zip(batch.check(&targets)) {
+            match result {
+                Ok(expiration) => {
+                    if !report(domain, &expiration) {
+                        exit_code = 1;
+                    }
+                }
+                Err(e) => report_error(domain, &e),
+            }
+        }
+        exit(exit_code);
+    }
+
+    for (domain, result) in domains.iter().zip(batch.check_each_address(&targets)) {
+        match result {
+            Ok(checks) => {
+                if !checks.is_consistent() {
+                    let _ = writeln!(stderr(),
+                                     "{} addresses do not all serve the same SSL certificate",
+                                     domain);
+                    exit_code = 1;
+                }
+                for (addr, result) in checks {
+                    let label = format!("{} ({})", domain, addr.ip());
+                    match result {
+                        Ok(expiration) => {
+                            if !report(&label, &expiration) {
+                                exit_code = 1;
+                            }
+                        }
+                        Err(e) => report_error(&label, &e),
+                    }
+                }
             }
+            Err(e) => report_error(domain, &e),
         }
     }
     exit(exit_code);
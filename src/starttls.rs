@@ -0,0 +1,951 @@
+//! Plain text negotiations upgrading a connection to TLS.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+
+use crate::deadline::DeadlineStream;
+use crate::error::{self, Error, Result};
+use crate::tds::{self, TdsStream};
+
+/// Protocol spoken on a connection before the TLS handshake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StartTls {
+    /// SMTP with the `STARTTLS` command (RFC 3207).
+    Smtp,
+    /// IMAP with the `STARTTLS` command (RFC 3501).
+    Imap,
+    /// POP3 with the `STLS` command (RFC 2595).
+    Pop3,
+    /// PostgreSQL with an `SSLRequest` message.
+    Postgres,
+    /// MySQL and MariaDB with an `SSLRequest` handshake response.
+    Mysql,
+    /// LDAP with the StartTLS extended operation (RFC 4511).
+    ///
+    /// LDAPS, where TLS starts with the connection, needs no upgrade and is
+    /// checked without `starttls` on port 636.
+    Ldap,
+    /// FTP with the `AUTH TLS` command (RFC 4217).
+    Ftp,
+    /// XMPP client connections with STARTTLS stream negotiation (RFC 6120).
+    Xmpp,
+    /// XMPP server-to-server connections with STARTTLS stream negotiation.
+    XmppServer,
+    /// NNTP with the `STARTTLS` command (RFC 4642).
+    Nntp,
+    /// Microsoft SQL Server, tunneling the TLS handshake through TDS
+    /// pre-login packets.
+    Tds,
+    /// RDP with an X.224 Connection Request asking for TLS or CredSSP.
+    Rdp,
+}
+
+impl StartTls {
+    /// Well known port of the protocol.
+    pub fn default_port(&self) -> u16 {
+        match *self {
+            StartTls::Smtp => 25,
+            StartTls::Imap => 143,
+            StartTls::Pop3 => 110,
+            StartTls::Postgres => 5432,
+            StartTls::Mysql => 3306,
+            StartTls::Ldap => 389,
+            StartTls::Ftp => 21,
+            StartTls::Xmpp => 5222,
+            StartTls::XmppServer => 5269,
+            StartTls::Nntp => 119,
+            StartTls::Tds => 1433,
+            StartTls::Rdp => 3389,
+        }
+    }
+
+    /// Upgrades `stream` so that it is ready for the TLS handshake, returning
+    /// the transport to run the handshake on.
+    /// `server_name` is announced to protocols that need it, like XMPP.
+    pub(crate) fn negotiate(&self,
+                            mut stream: DeadlineStream,
+                            server_name: Option<&str>)
+                            -> Result<Transport> {
+        let result = {
+            let stream = &mut stream;
+            match *self {
+                StartTls::Smtp => smtp(stream),
+                StartTls::Imap => imap(stream),
+                StartTls::Pop3 => pop3(stream),
+                StartTls::Postgres => postgres(stream),
+                StartTls::Mysql => mysql(stream),
+                StartTls::Ldap => ldap(stream),
+                StartTls::Ftp => ftp(stream),
+                StartTls::Xmpp => xmpp(stream, "jabber:client", server_name),
+                StartTls::XmppServer => xmpp(stream, "jabber:server", server_name),
+                StartTls::Nntp => nntp(stream),
+                StartTls::Tds => tds::prelogin(stream),
+                StartTls::Rdp => rdp(stream),
+            }
+        };
+        result.map_err(|e| match e {
+            Error::Io(ref e) if error::is_timeout(e) => {
+                Error::Timeout("during the STARTTLS negotiation")
+            }
+            e => e,
+        })?;
+        Ok(match *self {
+            StartTls::Tds => Transport::Tds(TdsStream::new(stream)),
+            _ => Transport::Plain(stream),
+        })
+    }
+}
+
+/// Connection the TLS handshake runs on.
+#[derive(Debug)]
+pub(crate) enum Transport {
+    Plain(DeadlineStream),
+    Tds(TdsStream<DeadlineStream>),
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Transport::Plain(ref mut s) => s.read(buf),
+            Transport::Tds(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Transport::Plain(ref mut s) => s.write(buf),
+            Transport::Tds(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Transport::Plain(ref mut s) => s.flush(),
+            Transport::Tds(ref mut s) => s.flush(),
+        }
+    }
+}
+
+impl fmt::Display for StartTls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StartTls::Smtp => write!(f, "smtp"),
+            StartTls::Imap => write!(f, "imap"),
+            StartTls::Pop3 => write!(f, "pop3"),
+            StartTls::Postgres => write!(f, "postgres"),
+            StartTls::Mysql => write!(f, "mysql"),
+            StartTls::Ldap => write!(f, "ldap"),
+            StartTls::Ftp => write!(f, "ftp"),
+            StartTls::Xmpp => write!(f, "xmpp"),
+            StartTls::XmppServer => write!(f, "xmpp-server"),
+            StartTls::Nntp => write!(f, "nntp"),
+            StartTls::Tds => write!(f, "tds"),
+            StartTls::Rdp => write!(f, "rdp"),
+        }
+    }
+}
+
+impl FromStr for StartTls {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<StartTls, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "smtp" => Ok(StartTls::Smtp),
+            "imap" => Ok(StartTls::Imap),
+            "pop3" => Ok(StartTls::Pop3),
+            "postgres" | "postgresql" => Ok(StartTls::Postgres),
+            "mysql" | "mariadb" => Ok(StartTls::Mysql),
+            "ldap" => Ok(StartTls::Ldap),
+            "ftp" => Ok(StartTls::Ftp),
+            "xmpp" | "xmpp-client" => Ok(StartTls::Xmpp),
+            "xmpp-server" => Ok(StartTls::XmppServer),
+            "nntp" => Ok(StartTls::Nntp),
+            "tds" | "mssql" | "sqlserver" => Ok(StartTls::Tds),
+            "rdp" => Ok(StartTls::Rdp),
+            _ => Err(format!("unknown STARTTLS protocol: {}", s)),
+        }
+    }
+}
+
+/// Reads a line, failing if the server closed the connection.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                            "server closed the connection")));
+    }
+    Ok(line.trim_end().to_owned())
+}
+
+/// Reads a possibly multi-line SMTP reply, returning its code and lines.
+fn read_smtp_reply<R: BufRead>(reader: &mut R) -> Result<(u16, Vec<String>)> {
+    let mut lines = vec![];
+    loop {
+        let line = read_line(reader)?;
+        let code = match line.get(..3).and_then(|c| c.parse().ok()) {
+            Some(code) => code,
+            None => return Err(Error::StartTls(format!("unexpected SMTP reply: {}", line))),
+        };
+        let last = line.as_bytes().get(3) != Some(&b'-');
+        lines.push(line[3..].trim_start_matches(['-', ' ']).to_owned());
+        if last {
+            return Ok((code, lines));
+        }
+    }
+}
+
+fn smtp(stream: &mut DeadlineStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let (code, lines) = read_smtp_reply(&mut reader)?;
+    if code != 220 {
+        return Err(Error::StartTls(format!("unexpected SMTP greeting: {} {}", code, lines.join(" "))));
+    }
+
+    stream.write_all(b"EHLO ssl-expiration\r\n")?;
+    let (code, lines) = read_smtp_reply(&mut reader)?;
+    if code != 250 {
+        return Err(Error::StartTls(format!("EHLO rejected: {} {}", code, lines.join(" "))));
+    }
+    if !lines.iter().any(|l| l.eq_ignore_ascii_case("STARTTLS")) {
+        return Err(Error::StartTlsNotSupported);
+    }
+
+    stream.write_all(b"STARTTLS\r\n")?;
+    let (code, lines) = read_smtp_reply(&mut reader)?;
+    if code != 220 {
+        return Err(Error::StartTls(format!("STARTTLS rejected: {} {}", code, lines.join(" "))));
+    }
+    Ok(())
+}
+
+/// Reads IMAP responses up to the tagged one, returning its status and the
+/// untagged responses before it.
+fn read_imap_response<R: BufRead>(reader: &mut R, tag: &str) -> Result<(String, Vec<String>)> {
+    let mut untagged = vec![];
+    loop {
+        let line = read_line(reader)?;
+        if let Some(response) = line.strip_prefix("* ") {
+            untagged.push(response.to_owned());
+        } else if let Some(status) = line.strip_prefix(tag).and_then(|l| l.strip_prefix(' ')) {
+            return Ok((status.to_owned(), untagged));
+        } else {
+            return Err(Error::StartTls(format!("unexpected IMAP response: {}", line)));
+        }
+    }
+}
+
+/// Returns true if an IMAP capability list contains `STARTTLS`.
+fn imap_has_starttls(capabilities: &str) -> bool {
+    capabilities.split([' ', '[', ']'])
+        .any(|c| c.eq_ignore_ascii_case("STARTTLS"))
+}
+
+fn imap(stream: &mut DeadlineStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let greeting = read_line(&mut reader)?;
+    if !greeting.starts_with("* OK") {
+        return Err(Error::StartTls(format!("unexpected IMAP greeting: {}", greeting)));
+    }
+
+    stream.write_all(b"a001 CAPABILITY\r\n")?;
+    let (status, untagged) = read_imap_response(&mut reader, "a001")?;
+    if !status.starts_with("OK") {
+        return Err(Error::StartTls(format!("CAPABILITY rejected: {}", status)));
+    }
+    if !untagged.iter().any(|l| imap_has_starttls(l)) {
+        return Err(Error::StartTlsNotSupported);
+    }
+
+    stream.write_all(b"a002 STARTTLS\r\n")?;
+    let (status, _) = read_imap_response(&mut reader, "a002")?;
+    if !status.starts_with("OK") {
+        return Err(Error::StartTls(format!("STARTTLS rejected: {}", status)));
+    }
+    Ok(())
+}
+
+fn pop3(stream: &mut DeadlineStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let greeting = read_line(&mut reader)?;
+    if !greeting.starts_with("+OK") {
+        return Err(Error::StartTls(format!("unexpected POP3 greeting: {}", greeting)));
+    }
+
+    // CAPA is optional, servers without it may still support STLS.
+    stream.write_all(b"CAPA\r\n")?;
+    if read_line(&mut reader)?.starts_with("+OK") {
+        let mut stls = false;
+        loop {
+            let line = read_line(&mut reader)?;
+            if line == "." {
+                break;
+            }
+            stls |= line.split(' ').next().is_some_and(|c| c.eq_ignore_ascii_case("STLS"));
+        }
+        if !stls {
+            return Err(Error::StartTlsNotSupported);
+        }
+    }
+
+    stream.write_all(b"STLS\r\n")?;
+    let reply = read_line(&mut reader)?;
+    if !reply.starts_with("+OK") {
+        return Err(Error::StartTlsNotSupported);
+    }
+    Ok(())
+}
+
+/// Request code of the PostgreSQL `SSLRequest` message.
+const POSTGRES_SSL_REQUEST: u32 = 80877103;
+
+fn postgres(stream: &mut DeadlineStream) -> Result<()> {
+    let mut request = [0; 8];
+    request[..4].copy_from_slice(&8u32.to_be_bytes());
+    request[4..].copy_from_slice(&POSTGRES_SSL_REQUEST.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0; 1];
+    stream.read_exact(&mut reply)?;
+    match reply[0] {
+        b'S' => Ok(()),
+        b'N' => Err(Error::StartTlsNotSupported),
+        b => Err(Error::StartTls(format!("unexpected PostgreSQL reply to SSLRequest: {:#04x}", b))),
+    }
+}
+
+// MySQL capability flags.
+const MYSQL_CLIENT_PROTOCOL_41: u32 = 0x0200;
+const MYSQL_CLIENT_SSL: u32 = 0x0800;
+const MYSQL_CLIENT_SECURE_CONNECTION: u32 = 0x8000;
+/// utf8_general_ci
+const MYSQL_CHARSET: u8 = 33;
+
+/// Reads a MySQL packet, returning its sequence id and payload.
+fn read_mysql_packet<R: Read>(reader: &mut R) -> Result<(u8, Vec<u8>)> {
+    let mut header = [0; 4];
+    reader.read_exact(&mut header)?;
+    let len = header[0] as usize | (header[1] as usize) << 8 | (header[2] as usize) << 16;
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok((header[3], payload))
+}
+
+/// Returns the capability flags of a MySQL initial handshake packet.
+fn mysql_capabilities(greeting: &[u8]) -> Result<u32> {
+    match greeting.first() {
+        Some(&10) => {}
+        Some(&0xff) if greeting.len() > 3 => {
+            // ERR packet: error code, optional SQL state and message.
+            let message = &greeting[3..];
+            let message = if message.first() == Some(&b'#') && message.len() >= 6 {
+                &message[6..]
+            } else {
+                message
+            };
+            return Err(Error::StartTls(format!("MySQL server refused connection: {}",
+                                               String::from_utf8_lossy(message))));
+        }
+        _ => return Err(Error::StartTls("unexpected MySQL initial handshake".to_owned())),
+    }
+
+    // Protocol version, NUL terminated server version, connection id,
+    // 8 bytes of auth data and a filler precede the lower capability flags.
+    let version_end = greeting[1..].iter().position(|&b| b == 0).map(|p| p + 1);
+    let flags = version_end.map(|end| end + 1 + 4 + 8 + 1);
+    let flags = match flags {
+        Some(flags) if greeting.len() >= flags + 2 => flags,
+        _ => return Err(Error::StartTls("truncated MySQL initial handshake".to_owned())),
+    };
+    let mut capabilities = greeting[flags] as u32 | (greeting[flags + 1] as u32) << 8;
+    // Character set and status flags precede the upper capability flags.
+    if greeting.len() >= flags + 7 {
+        capabilities |= (greeting[flags + 5] as u32) << 16 | (greeting[flags + 6] as u32) << 24;
+    }
+    Ok(capabilities)
+}
+
+fn mysql(stream: &mut DeadlineStream) -> Result<()> {
+    let (sequence, greeting) = read_mysql_packet(stream)?;
+    if mysql_capabilities(&greeting)? & MYSQL_CLIENT_SSL == 0 {
+        return Err(Error::StartTlsNotSupported);
+    }
+
+    // SSLRequest: capability flags, max packet size, character set and 23
+    // reserved bytes.
+    let capabilities = MYSQL_CLIENT_PROTOCOL_41 | MYSQL_CLIENT_SSL | MYSQL_CLIENT_SECURE_CONNECTION;
+    let mut packet = vec![32, 0, 0, sequence.wrapping_add(1)];
+    packet.extend_from_slice(&capabilities.to_le_bytes());
+    packet.extend_from_slice(&(16 * 1024 * 1024u32).to_le_bytes());
+    packet.push(MYSQL_CHARSET);
+    packet.extend_from_slice(&[0; 23]);
+    stream.write_all(&packet)?;
+    Ok(())
+}
+
+/// OID of the LDAP StartTLS extended operation.
+const LDAP_STARTTLS_OID: &[u8] = b"1.3.6.1.4.1.1466.20037";
+/// LDAP result code of servers that do not know an extended operation.
+const LDAP_PROTOCOL_ERROR: u32 = 2;
+
+/// Splits a BER encoded element off `data`, returning its tag, its contents
+/// and the rest of `data`.
+fn ber_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let octets = first & 0x7f;
+        if octets == 0 || octets > 4 {
+            return None;
+        }
+        let mut len = 0;
+        for &b in data.get(2..2 + octets)? {
+            len = len << 8 | b as usize;
+        }
+        (len, 2 + octets)
+    };
+    let contents = data.get(header..header + len)?;
+    Some((tag, contents, &data[header + len..]))
+}
+
+/// Reads one BER encoded element from `reader`.
+fn read_ber_element<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let mut element = header.to_vec();
+    let len = if header[1] < 0x80 {
+        header[1] as usize
+    } else {
+        let octets = (header[1] & 0x7f) as usize;
+        if octets == 0 || octets > 4 {
+            return Err(Error::StartTls("unsupported BER length in LDAP response".to_owned()));
+        }
+        let mut len_octets = vec![0; octets];
+        reader.read_exact(&mut len_octets)?;
+        element.extend_from_slice(&len_octets);
+        len_octets.iter().fold(0, |len, &b| len << 8 | b as usize)
+    };
+    let start = element.len();
+    element.resize(start + len, 0);
+    reader.read_exact(&mut element[start..])?;
+    Ok(element)
+}
+
+/// Extracts the result code and diagnostic message of an LDAP
+/// ExtendedResponse.
+fn ldap_extended_result(message: &[u8]) -> Option<(u32, String)> {
+    let (0x30, message, _) = ber_element(message)? else { return None };
+    let (0x02, _, message) = ber_element(message)? else { return None };
+    let (0x78, response, _) = ber_element(message)? else { return None };
+    let (0x0a, code, response) = ber_element(response)? else { return None };
+    let code = code.iter().fold(0, |code, &b| code << 8 | b as u32);
+    let (_, _, response) = ber_element(response)?;
+    let diagnostic = ber_element(response).map_or(String::new(), |(_, m, _)| {
+        String::from_utf8_lossy(m).into_owned()
+    });
+    Some((code, diagnostic))
+}
+
+fn ldap(stream: &mut DeadlineStream) -> Result<()> {
+    // LDAPMessage { messageID 1, ExtendedRequest { requestName } }
+    let mut request = vec![0x30, 0x1d, 0x02, 0x01, 0x01, 0x77, 0x18, 0x80, 0x16];
+    request.extend_from_slice(LDAP_STARTTLS_OID);
+    stream.write_all(&request)?;
+
+    let response = read_ber_element(stream)?;
+    match ldap_extended_result(&response) {
+        Some((0, _)) => Ok(()),
+        Some((LDAP_PROTOCOL_ERROR, _)) => Err(Error::StartTlsNotSupported),
+        Some((code, diagnostic)) => {
+            Err(Error::StartTls(format!("LDAP StartTLS failed with result code {}: {}",
+                                        code,
+                                        diagnostic)))
+        }
+        None => Err(Error::StartTls("unexpected LDAP response".to_owned())),
+    }
+}
+
+/// Reads a possibly multi-line FTP reply, returning its code and last line.
+///
+/// Unlike SMTP, only the first and the last line of a multi-line FTP reply
+/// start with the code.
+fn read_ftp_reply<R: BufRead>(reader: &mut R) -> Result<(u16, String)> {
+    let line = read_line(reader)?;
+    let code: u16 = match line.get(..3).and_then(|c| c.parse().ok()) {
+        Some(code) => code,
+        None => return Err(Error::StartTls(format!("unexpected FTP reply: {}", line))),
+    };
+    if line.as_bytes().get(3) != Some(&b'-') {
+        return Ok((code, line[3..].trim_start().to_owned()));
+    }
+    let end = format!("{} ", code);
+    loop {
+        let line = read_line(reader)?;
+        if let Some(text) = line.strip_prefix(&end) {
+            return Ok((code, text.to_owned()));
+        }
+    }
+}
+
+fn ftp(stream: &mut DeadlineStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let (code, text) = read_ftp_reply(&mut reader)?;
+    if code != 220 {
+        return Err(Error::StartTls(format!("unexpected FTP greeting: {} {}", code, text)));
+    }
+
+    stream.write_all(b"AUTH TLS\r\n")?;
+    match read_ftp_reply(&mut reader)? {
+        (234, _) => Ok(()),
+        (500..=504, _) | (534, _) => Err(Error::StartTlsNotSupported),
+        (code, text) => Err(Error::StartTls(format!("AUTH TLS rejected: {} {}", code, text))),
+    }
+}
+
+fn nntp(stream: &mut DeadlineStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let greeting = read_line(&mut reader)?;
+    if !greeting.starts_with("200") && !greeting.starts_with("201") {
+        return Err(Error::StartTls(format!("unexpected NNTP greeting: {}", greeting)));
+    }
+
+    stream.write_all(b"STARTTLS\r\n")?;
+    let reply = read_line(&mut reader)?;
+    if reply.starts_with("382") {
+        Ok(())
+    } else if reply.starts_with("500") || reply.starts_with("502") || reply.starts_with("580") {
+        Err(Error::StartTlsNotSupported)
+    } else {
+        Err(Error::StartTls(format!("STARTTLS rejected: {}", reply)))
+    }
+}
+
+/// Namespace of the XMPP STARTTLS elements.
+const XMPP_TLS_NS: &str = "urn:ietf:params:xml:ns:xmpp-tls";
+
+/// Reads from `reader` into `buf` until it contains one of `patterns`,
+/// returning the index of the pattern found.
+fn read_until_any<R: Read>(reader: &mut R, buf: &mut String, patterns: &[&str]) -> Result<usize> {
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(i) = patterns.iter().position(|p| buf.contains(p)) {
+            return Ok(i);
+        }
+        let n = reader.read(&mut chunk)?;
+        if n == 0 {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                "server closed the connection")));
+        }
+        buf.push_str(&String::from_utf8_lossy(&chunk[..n]));
+    }
+}
+
+fn xmpp(stream: &mut DeadlineStream, namespace: &str, server_name: Option<&str>) -> Result<()> {
+    let to = server_name.map_or(String::new(), |name| format!(" to='{}'", name));
+    write!(stream,
+           "<?xml version='1.0'?><stream:stream xmlns='{}' \
+            xmlns:stream='http://etherx.jabber.org/streams'{} version='1.0'>",
+           namespace,
+           to)?;
+
+    let mut features = String::new();
+    let found = read_until_any(stream,
+                               &mut features,
+                               &["</stream:features>", "<stream:features/>", "</stream:error>"])?;
+    if found == 2 {
+        return Err(Error::StartTls(format!("XMPP stream error: {}", features)));
+    }
+    if !features.contains(XMPP_TLS_NS) {
+        return Err(Error::StartTlsNotSupported);
+    }
+
+    write!(stream, "<starttls xmlns='{}'/>", XMPP_TLS_NS)?;
+    let mut reply = String::new();
+    match read_until_any(stream, &mut reply, &["<proceed", "<failure"])? {
+        0 => Ok(()),
+        _ => Err(Error::StartTls("XMPP server refused STARTTLS".to_owned())),
+    }
+}
+
+// RDP negotiation message types and protocol flags (MS-RDPBCGR 2.2.1).
+const RDP_NEG_REQ: u8 = 0x01;
+const RDP_NEG_RSP: u8 = 0x02;
+const RDP_NEG_FAILURE: u8 = 0x03;
+const RDP_PROTOCOL_SSL: u32 = 0x01;
+const RDP_PROTOCOL_HYBRID: u32 = 0x02;
+/// Failure code of servers that only allow standard RDP security.
+const RDP_SSL_NOT_ALLOWED_BY_SERVER: u32 = 0x02;
+
+/// Reads a TPKT packet (RFC 1006), returning its payload.
+fn read_tpkt<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut header = [0; 4];
+    reader.read_exact(&mut header)?;
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if header[0] != 3 || len < 4 {
+        return Err(Error::StartTls("unexpected RDP reply".to_owned()));
+    }
+    let mut payload = vec![0; len - 4];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+fn rdp(stream: &mut DeadlineStream) -> Result<()> {
+    // TPKT header, X.224 Connection Request and RDP Negotiation Request.
+    let mut request = vec![3, 0, 0, 19, 14, 0xe0, 0, 0, 0, 0, 0, RDP_NEG_REQ, 0, 8, 0];
+    request.extend_from_slice(&(RDP_PROTOCOL_SSL | RDP_PROTOCOL_HYBRID).to_le_bytes());
+    stream.write_all(&request)?;
+
+    // X.224 Connection Confirm, optionally followed by the negotiation reply.
+    let reply = read_tpkt(stream)?;
+    if reply.get(1).map(|code| code & 0xf0) != Some(0xd0) {
+        return Err(Error::StartTls("RDP server did not confirm the connection".to_owned()));
+    }
+    let negotiation = match reply.get(7..15) {
+        Some(negotiation) => negotiation,
+        // Servers only supporting standard RDP security send no negotiation.
+        None => return Err(Error::StartTlsNotSupported),
+    };
+    let value = u32::from_le_bytes([negotiation[4],
+                                     negotiation[5],
+                                     negotiation[6],
+                                     negotiation[7]]);
+    match negotiation[0] {
+        RDP_NEG_RSP if value & (RDP_PROTOCOL_SSL | RDP_PROTOCOL_HYBRID) != 0 => Ok(()),
+        RDP_NEG_RSP => Err(Error::StartTlsNotSupported),
+        RDP_NEG_FAILURE if value == RDP_SSL_NOT_ALLOWED_BY_SERVER => {
+            Err(Error::StartTlsNotSupported)
+        }
+        RDP_NEG_FAILURE => {
+            Err(Error::StartTls(format!("RDP negotiation failed with code {}", value)))
+        }
+        t => Err(Error::StartTls(format!("unexpected RDP negotiation type {:#04x}", t))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use crate::test_util;
+    use crate::Checker;
+
+    /// Speaks SMTP up to the STARTTLS command.
+    fn smtp_server(stream: &mut TcpStream, starttls: bool) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream.write_all(b"220 mail.example.test ESMTP\r\n").unwrap();
+        assert!(read_line(&mut reader).unwrap().starts_with("EHLO "));
+        stream.write_all(b"250-mail.example.test\r\n250-SIZE 1000000\r\n").unwrap();
+        if starttls {
+            stream.write_all(b"250-STARTTLS\r\n").unwrap();
+        }
+        stream.write_all(b"250 8BITMIME\r\n").unwrap();
+        if starttls {
+            assert_eq!(read_line(&mut reader).unwrap(), "STARTTLS");
+            stream.write_all(b"220 Ready to start TLS\r\n").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_smtp() {
+        let (cert, key) = test_util::self_signed("mail.example.test", 30);
+        let (addr, sni) = test_util::serve_after((cert, key), |s| smtp_server(s, true));
+        let expiration = Checker::new()
+            .starttls(StartTls::Smtp)
+            .check(addr, Some("mail.example.test"))
+            .unwrap();
+        assert_eq!(expiration.days(), 30);
+        assert_eq!(sni.recv().unwrap(), Some("mail.example.test".to_owned()));
+    }
+
+    #[test]
+    fn test_smtp_without_starttls() {
+        let (cert, key) = test_util::self_signed("mail.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| smtp_server(s, false));
+        test_util::assert_err!(Checker::new().starttls(StartTls::Smtp).check(addr, None),
+                               Error::StartTlsNotSupported);
+    }
+
+    #[test]
+    fn test_imap() {
+        let (cert, key) = test_util::self_signed("mail.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |stream| {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"* OK [CAPABILITY IMAP4rev1 LOGINDISABLED] ready\r\n").unwrap();
+            assert_eq!(read_line(&mut reader).unwrap(), "a001 CAPABILITY");
+            stream.write_all(b"* CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED\r\n").unwrap();
+            stream.write_all(b"a001 OK CAPABILITY completed\r\n").unwrap();
+            assert_eq!(read_line(&mut reader).unwrap(), "a002 STARTTLS");
+            stream.write_all(b"a002 OK Begin TLS negotiation now\r\n").unwrap();
+        });
+        let expiration = Checker::new().starttls(StartTls::Imap).check(addr, None).unwrap();
+        assert_eq!(expiration.days(), 30);
+    }
+
+    #[test]
+    fn test_pop3() {
+        let (cert, key) = test_util::self_signed("mail.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |stream| {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"+OK POP3 ready\r\n").unwrap();
+            assert_eq!(read_line(&mut reader).unwrap(), "CAPA");
+            stream.write_all(b"+OK\r\nUSER\r\nSTLS\r\n.\r\n").unwrap();
+            assert_eq!(read_line(&mut reader).unwrap(), "STLS");
+            stream.write_all(b"+OK Begin TLS negotiation\r\n").unwrap();
+        });
+        let expiration = Checker::new().starttls(StartTls::Pop3).check(addr, None).unwrap();
+        assert_eq!(expiration.days(), 30);
+
+        let (cert, key) = test_util::self_signed("mail.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |stream| {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"+OK POP3 ready\r\n").unwrap();
+            assert_eq!(read_line(&mut reader).unwrap(), "CAPA");
+            stream.write_all(b"+OK\r\nUSER\r\n.\r\n").unwrap();
+        });
+        test_util::assert_err!(Checker::new().starttls(StartTls::Pop3).check(addr, None),
+                               Error::StartTlsNotSupported);
+    }
+
+    /// Reads a PostgreSQL SSLRequest and answers it with `reply`.
+    fn postgres_server(stream: &mut TcpStream, reply: u8) {
+        let mut request = [0; 8];
+        stream.read_exact(&mut request).unwrap();
+        assert_eq!(request, [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]);
+        stream.write_all(&[reply]).unwrap();
+    }
+
+    #[test]
+    fn test_postgres() {
+        let (cert, key) = test_util::self_signed("db.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| postgres_server(s, b'S'));
+        let expiration = Checker::new().starttls(StartTls::Postgres).check(addr, None).unwrap();
+        assert_eq!(expiration.days(), 30);
+
+        let (cert, key) = test_util::self_signed("db.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| postgres_server(s, b'N'));
+        test_util::assert_err!(Checker::new().starttls(StartTls::Postgres).check(addr, None),
+                               Error::StartTlsNotSupported);
+    }
+
+    /// Sends a MySQL initial handshake with the given capabilities and reads
+    /// the SSLRequest if TLS is supported.
+    fn mysql_server(stream: &mut TcpStream, capabilities: u32) {
+        let mut greeting = vec![10];
+        greeting.extend_from_slice(b"8.0.36\0");
+        greeting.extend_from_slice(&[1, 0, 0, 0]);
+        greeting.extend_from_slice(b"abcdefgh\0");
+        greeting.extend_from_slice(&(capabilities as u16).to_le_bytes());
+        greeting.extend_from_slice(&[MYSQL_CHARSET, 2, 0]);
+        greeting.extend_from_slice(&((capabilities >> 16) as u16).to_le_bytes());
+        greeting.extend_from_slice(&[21]);
+        greeting.extend_from_slice(&[0; 10]);
+        greeting.extend_from_slice(b"ijklmnopqrst\0mysql_native_password\0");
+        let mut packet = vec![greeting.len() as u8, 0, 0, 0];
+        packet.extend_from_slice(&greeting);
+        stream.write_all(&packet).unwrap();
+
+        if capabilities & MYSQL_CLIENT_SSL != 0 {
+            let (sequence, request) = read_mysql_packet(stream).unwrap();
+            assert_eq!(sequence, 1);
+            assert_eq!(request.len(), 32);
+            let flags = u32::from_le_bytes([request[0], request[1], request[2], request[3]]);
+            assert!(flags & MYSQL_CLIENT_SSL != 0);
+        }
+    }
+
+    #[test]
+    fn test_mysql() {
+        let (cert, key) = test_util::self_signed("db.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| mysql_server(s, 0x000fffff));
+        let expiration = Checker::new().starttls(StartTls::Mysql).check(addr, None).unwrap();
+        assert_eq!(expiration.days(), 30);
+
+        let (cert, key) = test_util::self_signed("db.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| mysql_server(s, 0x000ff7ff));
+        test_util::assert_err!(Checker::new().starttls(StartTls::Mysql).check(addr, None),
+                               Error::StartTlsNotSupported);
+    }
+
+    /// Reads an LDAP StartTLS request and answers it with `code`.
+    fn ldap_server(stream: &mut TcpStream, code: u8) {
+        let request = read_ber_element(stream).unwrap();
+        assert_eq!(&request[..9], &[0x30, 0x1d, 0x02, 0x01, 0x01, 0x77, 0x18, 0x80, 0x16]);
+        assert_eq!(&request[9..], LDAP_STARTTLS_OID);
+        let mut response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, code,
+                                0x04, 0x00, 0x04, 0x00];
+        if code != 0 {
+            response[1] += 4;
+            response[6] += 4;
+            response.truncate(12);
+            response.extend_from_slice(&[0x04, 0x04, b'n', b'o', b'p', b'e']);
+        }
+        stream.write_all(&response).unwrap();
+    }
+
+    #[test]
+    fn test_ldap() {
+        let (cert, key) = test_util::self_signed("ldap.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| ldap_server(s, 0));
+        let expiration = Checker::new().starttls(StartTls::Ldap).check(addr, None).unwrap();
+        assert_eq!(expiration.days(), 30);
+
+        let (cert, key) = test_util::self_signed("ldap.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| ldap_server(s, 2));
+        test_util::assert_err!(Checker::new().starttls(StartTls::Ldap).check(addr, None),
+                               Error::StartTlsNotSupported);
+
+        let (cert, key) = test_util::self_signed("ldap.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| ldap_server(s, 52));
+        test_util::assert_err!(Checker::new().starttls(StartTls::Ldap).check(addr, None),
+                               Error::StartTls(e) if e.contains("52: nope"));
+    }
+
+    #[test]
+    fn test_ftp() {
+        let (cert, key) = test_util::self_signed("ftp.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |stream| {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"220-Welcome\r\n  to the FTP server\r\n220 Ready\r\n").unwrap();
+            assert_eq!(read_line(&mut reader).unwrap(), "AUTH TLS");
+            stream.write_all(b"234 AUTH TLS successful\r\n").unwrap();
+        });
+        let expiration = Checker::new().starttls(StartTls::Ftp).check(addr, None).unwrap();
+        assert_eq!(expiration.days(), 30);
+
+        let (cert, key) = test_util::self_signed("ftp.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |stream| {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"220 Ready\r\n").unwrap();
+            assert_eq!(read_line(&mut reader).unwrap(), "AUTH TLS");
+            stream.write_all(b"502 Command not implemented\r\n").unwrap();
+        });
+        test_util::assert_err!(Checker::new().starttls(StartTls::Ftp).check(addr, None),
+                               Error::StartTlsNotSupported);
+    }
+
+    #[test]
+    fn test_nntp() {
+        let (cert, key) = test_util::self_signed("news.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |stream| {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"200 news.example.test ready\r\n").unwrap();
+            assert_eq!(read_line(&mut reader).unwrap(), "STARTTLS");
+            stream.write_all(b"382 Continue with TLS negotiation\r\n").unwrap();
+        });
+        let expiration = Checker::new().starttls(StartTls::Nntp).check(addr, None).unwrap();
+        assert_eq!(expiration.days(), 30);
+    }
+
+    /// Speaks XMPP up to the STARTTLS proceed, checking the stream namespace.
+    fn xmpp_server(stream: &mut TcpStream, namespace: &'static str, starttls: bool) {
+        let mut header = String::new();
+        read_until_any(stream, &mut header, &["version='1.0'>"]).unwrap();
+        assert!(header.contains(&format!("xmlns='{}'", namespace)), "{}", header);
+        assert!(header.contains("to='chat.example.test'"), "{}", header);
+        stream.write_all(b"<?xml version='1.0'?><stream:stream from='chat.example.test' \
+                           id='1' version='1.0' xmlns:stream='http://etherx.jabber.org/streams'>")
+            .unwrap();
+        if !starttls {
+            stream.write_all(b"<stream:features><bind/></stream:features>").unwrap();
+            return;
+        }
+        stream.write_all(b"<stream:features><starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'>\
+                           <required/></starttls></stream:features>")
+            .unwrap();
+        let mut request = String::new();
+        read_until_any(stream, &mut request, &["/>"]).unwrap();
+        assert!(request.starts_with("<starttls"), "{}", request);
+        stream.write_all(b"<proceed xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>").unwrap();
+    }
+
+    #[test]
+    fn test_xmpp() {
+        let (cert, key) = test_util::self_signed("chat.example.test", 30);
+        let (addr, sni) =
+            test_util::serve_after((cert, key), |s| xmpp_server(s, "jabber:client", true));
+        let expiration = Checker::new()
+            .starttls(StartTls::Xmpp)
+            .check(addr, Some("chat.example.test"))
+            .unwrap();
+        assert_eq!(expiration.days(), 30);
+        assert_eq!(sni.recv().unwrap(), Some("chat.example.test".to_owned()));
+
+        let (cert, key) = test_util::self_signed("chat.example.test", 30);
+        let (addr, _) =
+            test_util::serve_after((cert, key), |s| xmpp_server(s, "jabber:server", true));
+        let expiration = Checker::new()
+            .starttls(StartTls::XmppServer)
+            .check(addr, Some("chat.example.test"))
+            .unwrap();
+        assert_eq!(expiration.days(), 30);
+
+        let (cert, key) = test_util::self_signed("chat.example.test", 30);
+        let (addr, _) =
+            test_util::serve_after((cert, key), |s| xmpp_server(s, "jabber:client", false));
+        let result = Checker::new().starttls(StartTls::Xmpp).check(addr, Some("chat.example.test"));
+        test_util::assert_err!(result, Error::StartTlsNotSupported);
+    }
+
+    /// Reads an X.224 Connection Request and confirms it with a negotiation
+    /// reply of `kind` and `value`.
+    fn rdp_server(stream: &mut TcpStream, kind: u8, value: u32) {
+        let request = read_tpkt(stream).unwrap();
+        assert_eq!(request[1], 0xe0);
+        assert_eq!(&request[7..11], &[RDP_NEG_REQ, 0, 8, 0]);
+        assert_eq!(request[11] as u32, RDP_PROTOCOL_SSL | RDP_PROTOCOL_HYBRID);
+        let mut reply = vec![3, 0, 0, 19, 14, 0xd0, 0, 0, 0x12, 0x34, 0, kind, 0, 8, 0];
+        reply.extend_from_slice(&value.to_le_bytes());
+        stream.write_all(&reply).unwrap();
+    }
+
+    #[test]
+    fn test_rdp() {
+        let (cert, key) = test_util::self_signed("rdp.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| {
+            rdp_server(s, RDP_NEG_RSP, RDP_PROTOCOL_HYBRID)
+        });
+        let expiration = Checker::new().starttls(StartTls::Rdp).check(addr, None).unwrap();
+        assert_eq!(expiration.days(), 30);
+
+        let (cert, key) = test_util::self_signed("rdp.example.test", 30);
+        let (addr, _) = test_util::serve_after((cert, key), |s| {
+            rdp_server(s, RDP_NEG_FAILURE, RDP_SSL_NOT_ALLOWED_BY_SERVER)
+        });
+        test_util::assert_err!(Checker::new().starttls(StartTls::Rdp).check(addr, None),
+                               Error::StartTlsNotSupported);
+    }
+
+    #[test]
+    fn test_ber_element() {
+        assert_eq!(ber_element(&[0x04, 0x02, 1, 2, 9]), Some((0x04, &[1, 2][..], &[9][..])));
+        assert_eq!(ber_element(&[0x04, 0x81, 0x02, 1, 2]), Some((0x04, &[1, 2][..], &[][..])));
+        assert_eq!(ber_element(&[0x04, 0x03, 1, 2]), None);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("SMTP".parse::<StartTls>(), Ok(StartTls::Smtp));
+        assert_eq!("imap".parse::<StartTls>(), Ok(StartTls::Imap));
+        assert_eq!("pop3".parse::<StartTls>(), Ok(StartTls::Pop3));
+        assert_eq!("postgres".parse::<StartTls>(), Ok(StartTls::Postgres));
+        assert_eq!("mariadb".parse::<StartTls>(), Ok(StartTls::Mysql));
+        assert_eq!("ldap".parse::<StartTls>(), Ok(StartTls::Ldap));
+        assert_eq!("ftp".parse::<StartTls>(), Ok(StartTls::Ftp));
+        assert_eq!("xmpp".parse::<StartTls>(), Ok(StartTls::Xmpp));
+        assert_eq!("xmpp-server".parse::<StartTls>(), Ok(StartTls::XmppServer));
+        assert_eq!("nntp".parse::<StartTls>(), Ok(StartTls::Nntp));
+        assert_eq!("mssql".parse::<StartTls>(), Ok(StartTls::Tds));
+        assert_eq!("rdp".parse::<StartTls>(), Ok(StartTls::Rdp));
+        assert!("gopher".parse::<StartTls>().is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! Microsoft SQL Server TDS pre-login, which carries the TLS handshake inside
+//! TDS packets.
+
+use std::io::{self, Read, Write};
+
+use crate::deadline::DeadlineStream;
+use crate::error::{Error, Result};
+
+/// Packet type of pre-login messages and of the tunneled TLS handshake.
+const PRELOGIN: u8 = 0x12;
+/// Packet type of the server's reply to a pre-login message.
+const TABULAR_RESULT: u8 = 0x04;
+/// Status flag of the last packet of a message.
+const END_OF_MESSAGE: u8 = 0x01;
+/// Size of a packet header.
+const HEADER_LEN: usize = 8;
+/// Default negotiated packet size.
+const PACKET_SIZE: usize = 4096;
+
+// Pre-login option tokens.
+const OPTION_VERSION: u8 = 0x00;
+const OPTION_ENCRYPTION: u8 = 0x01;
+const OPTION_TERMINATOR: u8 = 0xff;
+
+// Values of the encryption option.
+const ENCRYPT_ON: u8 = 0x01;
+const ENCRYPT_NOT_SUP: u8 = 0x02;
+
+/// Writes `payload` as a message of `kind` packets.
+fn write_message<W: Write>(writer: &mut W,
+                           kind: u8,
+                           payload: &[u8],
+                           packet_id: &mut u8)
+                           -> io::Result<()> {
+    let mut chunks = payload.chunks(PACKET_SIZE - HEADER_LEN).peekable();
+    while let Some(chunk) = chunks.next() {
+        let status = if chunks.peek().is_none() { END_OF_MESSAGE } else { 0 };
+        let len = (chunk.len() + HEADER_LEN) as u16;
+        let mut packet = vec![kind, status];
+        packet.extend_from_slice(&len.to_be_bytes());
+        // SPID, packet id and window.
+        packet.extend_from_slice(&[0, 0, *packet_id, 0]);
+        packet.extend_from_slice(chunk);
+        writer.write_all(&packet)?;
+        *packet_id = packet_id.wrapping_add(1);
+    }
+    Ok(())
+}
+
+/// Reads a packet, returning its type, status and payload.
+fn read_packet<R: Read>(reader: &mut R) -> io::Result<(u8, u8, Vec<u8>)> {
+    let mut header = [0; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if len < HEADER_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid TDS packet length"));
+    }
+    let mut payload = vec![0; len - HEADER_LEN];
+    reader.read_exact(&mut payload)?;
+    Ok((header[0], header[1], payload))
+}
+
+/// Returns the value of the encryption option of a pre-login message.
+fn encryption(message: &[u8]) -> Option<u8> {
+    let mut options = message;
+    loop {
+        match *options.first()? {
+            OPTION_TERMINATOR => return None,
+            token if options.len() >= 5 => {
+                let offset = u16::from_be_bytes([options[1], options[2]]) as usize;
+                let len = u16::from_be_bytes([options[3], options[4]]) as usize;
+                if token == OPTION_ENCRYPTION && len >= 1 {
+                    return message.get(offset).cloned();
+                }
+                options = &options[5..];
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Sends a pre-login message asking for encryption and checks that the
+/// server supports it.
+pub fn prelogin(stream: &mut DeadlineStream) -> Result<()> {
+    // Option table of version and encryption, followed by their values.
+    let mut message = vec![OPTION_VERSION, 0, 11, 0, 6, OPTION_ENCRYPTION, 0, 17, 0, 1,
+                           OPTION_TERMINATOR];
+    message.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    message.push(ENCRYPT_ON);
+    let mut packet_id = 1;
+    write_message(stream, PRELOGIN, &message, &mut packet_id)?;
+
+    let mut reply = vec![];
+    loop {
+        let (kind, status, payload) = read_packet(stream)?;
+        if kind != TABULAR_RESULT {
+            return Err(Error::StartTls(format!("unexpected TDS packet type {:#04x}", kind)));
+        }
+        reply.extend_from_slice(&payload);
+        if status & END_OF_MESSAGE != 0 {
+            break;
+        }
+    }
+    match encryption(&reply) {
+        Some(ENCRYPT_NOT_SUP) => Err(Error::StartTlsNotSupported),
+        Some(_) => Ok(()),
+        None => Err(Error::StartTls("TDS pre-login reply without encryption option".to_owned())),
+    }
+}
+
+/// Stream tunneling the TLS handshake through TDS pre-login packets.
+///
+/// Writes are buffered until OpenSSL flushes a flight of handshake messages,
+/// which is then sent as one pre-login message.
+#[derive(Debug)]
+pub struct TdsStream<S> {
+    stream: S,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    write_buf: Vec<u8>,
+    packet_id: u8,
+}
+
+impl<S: Read + Write> TdsStream<S> {
+    pub fn new(stream: S) -> TdsStream<S> {
+        TdsStream {
+            stream,
+            read_buf: vec![],
+            read_pos: 0,
+            write_buf: vec![],
+            packet_id: 1,
+        }
+    }
+}
+
+impl<S: Read + Write> Read for TdsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read_pos == self.read_buf.len() {
+            let (_, _, payload) = read_packet(&mut self.stream)?;
+            self.read_buf = payload;
+            self.read_pos = 0;
+        }
+        let n = buf.len().min(self.read_buf.len() - self.read_pos);
+        buf[..n].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + n]);
+        self.read_pos += n;
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for TdsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.write_buf.is_empty() {
+            write_message(&mut self.stream, PRELOGIN, &self.write_buf, &mut self.packet_id)?;
+            self.write_buf.clear();
+        }
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+    use crate::starttls::StartTls;
+    use crate::test_util;
+    use crate::Checker;
+
+    /// Answers a pre-login message with the given encryption option.
+    fn prelogin_server(stream: &mut TcpStream, encrypt: u8) {
+        let (kind, status, request) = read_packet(stream).unwrap();
+        assert_eq!((kind, status), (PRELOGIN, END_OF_MESSAGE));
+        assert_eq!(encryption(&request), Some(ENCRYPT_ON));
+        let mut reply = vec![OPTION_VERSION, 0, 11, 0, 6, OPTION_ENCRYPTION, 0, 17, 0, 1,
+                             OPTION_TERMINATOR, 16, 0, 0x10, 0, 0, 0];
+        reply.push(encrypt);
+        write_message(stream, TABULAR_RESULT, &reply, &mut 1).unwrap();
+    }
+
+    #[test]
+    fn test_tds() {
+        let (cert, key) = test_util::self_signed("sql.example.test", 30);
+        let (addr, sni) = test_util::serve_wrapped((cert, key), |mut stream| {
+            prelogin_server(&mut stream, ENCRYPT_ON);
+            TdsStream::new(stream)
+        });
+        let expiration = Checker::new()
+            .starttls(StartTls::Tds)
+            .check(addr, Some("sql.example.test"))
+            .unwrap();
+        assert_eq!(expiration.days(), 30);
+        assert_eq!(sni.recv().unwrap(), Some("sql.example.test".to_owned()));
+
+        let (cert, key) = test_util::self_signed("sql.example.test", 30);
+        let (addr, _) =
+            test_util::serve_after((cert, key), |s| prelogin_server(s, ENCRYPT_NOT_SUP));
+        test_util::assert_err!(Checker::new().starttls(StartTls::Tds).check(addr, None),
+                               Error::StartTlsNotSupported);
+    }
+
+    #[test]
+    fn test_encryption() {
+        assert_eq!(encryption(&[OPTION_ENCRYPTION, 0, 6, 0, 1, OPTION_TERMINATOR, 3]), Some(3));
+        assert_eq!(encryption(&[OPTION_VERSION, 0, 6, 0, 1, OPTION_TERMINATOR, 3]), None);
+        assert_eq!(encryption(&[OPTION_ENCRYPTION, 0, 9, 0, 1, OPTION_TERMINATOR]), None);
+    }
+}
@@ -0,0 +1,183 @@
+//! Helpers for tests: throwaway certificates and a local TLS server.
+
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{NameType, SniError, SslAcceptor, SslAlert, SslMethod};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509, X509NameBuilder};
+
+pub type Identity = (X509, PKey<Private>);
+
+/// Asserts that a result is an error matching the pattern.
+macro_rules! assert_err {
+    ($result:expr, $pattern:pat $(if $guard:expr)?) => {
+        match &$result {
+            Err($pattern) $(if $guard)? => {}
+            r => panic!("unexpected result: {:?}", r.as_ref().map(|_| ())),
+        }
+    };
+}
+
+pub(crate) use assert_err;
+
+/// Generates a certificate for `cn`, valid from `not_before` to `not_after`
+/// days relative to now.
+///
+/// A minute is added to the end of the validity period so that the days left
+/// do not depend on how long the test takes.
+///
+/// The certificate is self-signed unless an `issuer` is given.
+pub fn certificate(cn: &str,
+                   not_before: i64,
+                   not_after: i64,
+                   ca: bool,
+                   issuer: Option<&Identity>)
+                   -> Identity {
+    let key = PKey::from_ec_key(
+        EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap(),
+    ).unwrap();
+
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", cn).unwrap();
+    let name = name.build();
+
+    let mut serial = BigNum::new().unwrap();
+    serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_serial_number(&serial.to_asn1_integer().unwrap()).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(issuer.map_or(&name, |i| i.0.subject_name())).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder.set_not_before(&Asn1Time::from_unix(now + not_before * 86400).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::from_unix(now + not_after * 86400 + 60).unwrap()).unwrap();
+    if ca {
+        builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+            .unwrap();
+    } else {
+        let mut san = SubjectAlternativeName::new();
+        if cn.parse::<IpAddr>().is_ok() {
+            san.ip(cn);
+        } else {
+            san.dns(cn);
+        }
+        let san = san.build(&builder.x509v3_context(issuer.map(|i| &*i.0), None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+    }
+    builder.sign(issuer.map_or(&key, |i| &i.1), MessageDigest::sha256()).unwrap();
+
+    (builder.build(), key)
+}
+
+/// Generates a self-signed certificate valid for `days` days.
+pub fn self_signed(cn: &str, days: i64) -> Identity {
+    certificate(cn, 0, days, false, None)
+}
+
+/// Writes `contents` to a new file in the temporary directory.
+pub fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = env::temp_dir().join(format!("ssl-expiration-test-{}-{}",
+                                           std::process::id(),
+                                           COUNTER.fetch_add(1, Ordering::SeqCst)));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Accepts one TLS connection on a local port.
+///
+/// Returns the listening address and a receiver yielding the SNI name sent
+/// by the client.
+pub fn serve(cert: X509, key: PKey<Private>) -> (SocketAddr, Receiver<Option<String>>) {
+    serve_chain((cert, key), vec![])
+}
+
+/// Like `serve`, also sending the `chain` certificates after the leaf.
+pub fn serve_chain(identity: Identity, chain: Vec<X509>) -> (SocketAddr, Receiver<Option<String>>) {
+    serve_with(identity, chain, |stream| stream)
+}
+
+/// Like `serve`, running `negotiate` on the connection before the TLS
+/// handshake.
+pub fn serve_after<F>(identity: Identity, negotiate: F) -> (SocketAddr, Receiver<Option<String>>)
+    where F: FnOnce(&mut TcpStream) + Send + 'static
+{
+    serve_with(identity, vec![], move |mut stream| {
+        negotiate(&mut stream);
+        stream
+    })
+}
+
+/// Like `serve`, running the TLS handshake on the stream returned by `wrap`.
+pub fn serve_wrapped<F, S>(identity: Identity, wrap: F) -> (SocketAddr, Receiver<Option<String>>)
+    where F: FnOnce(TcpStream) -> S + Send + 'static,
+          S: Read + Write
+{
+    serve_with(identity, vec![], wrap)
+}
+
+/// Accepts one TLS connection on a local port and aborts the handshake with
+/// a fatal `unrecognized_name` alert, like a server not hosting the requested
+/// name.
+pub fn serve_unrecognized_name(identity: Identity) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&identity.0).unwrap();
+        acceptor.set_private_key(&identity.1).unwrap();
+        acceptor.set_servername_callback(|_, alert| {
+            *alert = SslAlert::UNRECOGNIZED_NAME;
+            Err(SniError::ALERT_FATAL)
+        });
+        let acceptor = acceptor.build();
+        let (stream, _) = listener.accept().unwrap();
+        let _ = acceptor.accept(stream);
+    });
+    addr
+}
+
+fn serve_with<F, S>(identity: Identity,
+                    chain: Vec<X509>,
+                    wrap: F)
+                    -> (SocketAddr, Receiver<Option<String>>)
+    where F: FnOnce(TcpStream) -> S + Send + 'static,
+          S: Read + Write
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&identity.0).unwrap();
+        acceptor.set_private_key(&identity.1).unwrap();
+        for cert in chain {
+            acceptor.add_extra_chain_cert(cert).unwrap();
+        }
+        let acceptor = acceptor.build();
+        let (stream, _) = listener.accept().unwrap();
+        if let Ok(stream) = acceptor.accept(wrap(stream)) {
+            let _ = tx.send(stream.ssl().servername(NameType::HOST_NAME).map(|n| n.to_owned()));
+        }
+    });
+    (addr, rx)
+}
@@ -0,0 +1,148 @@
+//! Signed time spans and validity periods of certificates.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+
+use crate::error::Result;
+
+/// A span of time that can be negative, e.g. the time left until a
+/// certificate expires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SignedDuration {
+    secs: i64,
+}
+
+impl SignedDuration {
+    /// Creates a duration of `secs` seconds.
+    pub fn from_secs(secs: i64) -> SignedDuration {
+        SignedDuration { secs }
+    }
+
+    /// Whole seconds of the duration.
+    pub fn as_secs(&self) -> i64 {
+        self.secs
+    }
+
+    /// Whole days of the duration, rounded towards zero.
+    pub fn as_days(&self) -> i64 {
+        self.secs / 60 / 60 / 24
+    }
+
+    /// Returns true if the duration is below zero.
+    pub fn is_negative(&self) -> bool {
+        self.secs < 0
+    }
+
+    /// Absolute value of the duration.
+    pub fn abs(&self) -> Duration {
+        Duration::from_secs(self.secs.unsigned_abs())
+    }
+}
+
+impl fmt::Display for SignedDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}s", self.secs)
+    }
+}
+
+/// Validity state of a certificate at the time of the check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validity {
+    /// The validity period has not started yet.
+    NotYetValid,
+    /// The certificate is valid.
+    Valid,
+    /// The certificate is valid but expires soon.
+    Expiring,
+    /// The validity period is over.
+    Expired,
+}
+
+impl Validity {
+    /// Returns the state of a certificate valid from `not_before` to
+    /// `not_after` at `now`, all in Unix seconds. The certificate is expiring
+    /// if it expires within `expiring_within`.
+    pub(crate) fn at(not_before: i64,
+                     not_after: i64,
+                     now: i64,
+                     expiring_within: Duration)
+                     -> Validity {
+        if now < not_before {
+            Validity::NotYetValid
+        } else if not_after < now {
+            Validity::Expired
+        } else if ((not_after - now) as u64) <= expiring_within.as_secs() {
+            Validity::Expiring
+        } else {
+            Validity::Valid
+        }
+    }
+}
+
+impl fmt::Display for Validity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Validity::NotYetValid => write!(f, "not yet valid"),
+            Validity::Valid => write!(f, "valid"),
+            Validity::Expiring => write!(f, "expiring"),
+            Validity::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+/// Converts an ASN.1 time to seconds since the Unix epoch.
+pub fn unix_secs(time: &Asn1TimeRef) -> Result<i64> {
+    let diff = Asn1Time::from_unix(0)?.diff(time)?;
+    Ok(diff.days as i64 * 24 * 60 * 60 + diff.secs as i64)
+}
+
+/// Current time in seconds since the Unix epoch.
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// Converts seconds since the Unix epoch to a `SystemTime`.
+pub fn system_time(secs: i64) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_secs() {
+        let time = Asn1Time::from_str("21500101000000Z").unwrap();
+        assert_eq!(unix_secs(&time).unwrap(), 5680281600);
+        let time = Asn1Time::from_str("19500101000000Z").unwrap();
+        assert_eq!(unix_secs(&time).unwrap(), -631152000);
+        assert_eq!(system_time(-631152000), UNIX_EPOCH - Duration::from_secs(631152000));
+    }
+
+    #[test]
+    fn test_validity() {
+        let week = Duration::from_secs(7 * 86400);
+        assert_eq!(Validity::at(100, 1000000, 50, week), Validity::NotYetValid);
+        assert_eq!(Validity::at(100, 1000000, 100, week), Validity::Valid);
+        assert_eq!(Validity::at(100, 1000000, 1000000 - 7 * 86400, week), Validity::Expiring);
+        assert_eq!(Validity::at(100, 1000000, 1000000, week), Validity::Expiring);
+        assert_eq!(Validity::at(100, 1000000, 1000001, week), Validity::Expired);
+    }
+
+    #[test]
+    fn test_signed_duration() {
+        let d = SignedDuration::from_secs(-2 * 86400 - 5);
+        assert_eq!(d.as_days(), -2);
+        assert!(d.is_negative());
+        assert_eq!(d.abs(), Duration::from_secs(2 * 86400 + 5));
+    }
+}
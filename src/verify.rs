@@ -0,0 +1,131 @@
+//! Validation of the served chain against the system and custom trust stores.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use openssl::ssl::SslFiletype;
+use openssl::stack::StackRef;
+use openssl::x509::store::{X509Lookup, X509Store, X509StoreBuilder, X509StoreRef};
+use openssl::x509::{X509, X509StoreContext, X509VerifyResult};
+use crate::error::Result;
+
+/// Outcome of validating the served chain against the trust store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyResult {
+    /// The chain leads to a trusted root.
+    Trusted(TrustAnchor),
+    /// The chain ends in a root that is not in the trust store.
+    UntrustedRoot,
+    /// The issuer of the leaf certificate was not found, usually because the
+    /// server did not send the intermediate certificates needed to reach a
+    /// trusted root. A leaf issued directly by an untrusted root is reported
+    /// the same way.
+    MissingIntermediate,
+    /// The leaf certificate is self-signed and not trusted.
+    SelfSigned,
+    /// A certificate of the chain is expired.
+    Expired,
+    /// A certificate of the chain is not valid yet.
+    NotYetValid,
+    /// A certificate of the chain is revoked.
+    Revoked,
+    /// Any other OpenSSL verification error.
+    Other {
+        /// OpenSSL `X509_V_ERR_*` code.
+        code: i32,
+        /// OpenSSL description of the error.
+        reason: String,
+    },
+}
+
+/// Where the root of a trusted chain comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrustAnchor {
+    /// The system trust store.
+    System,
+    /// CA certificates given with `Checker::ca_file` or `Checker::ca_dir`.
+    Custom,
+}
+
+impl VerifyResult {
+    /// Maps the first verification error reported by OpenSSL, and the depth
+    /// in the chain it was reported at, to a `VerifyResult`.
+    pub(crate) fn from_error(error: X509VerifyResult, depth: u32) -> VerifyResult {
+        match error.as_raw() {
+            openssl_sys::X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT => VerifyResult::SelfSigned,
+            openssl_sys::X509_V_ERR_SELF_SIGNED_CERT_IN_CHAIN => VerifyResult::UntrustedRoot,
+            openssl_sys::X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT |
+            openssl_sys::X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY |
+            openssl_sys::X509_V_ERR_UNABLE_TO_VERIFY_LEAF_SIGNATURE => {
+                // When the issuer of the leaf itself can not be found the
+                // server most likely did not send its intermediates, the
+                // issuing root could as well be unknown. Otherwise the top of
+                // the served chain is not trusted.
+                if depth == 0 {
+                    VerifyResult::MissingIntermediate
+                } else {
+                    VerifyResult::UntrustedRoot
+                }
+            }
+            openssl_sys::X509_V_ERR_CERT_HAS_EXPIRED => VerifyResult::Expired,
+            openssl_sys::X509_V_ERR_CERT_NOT_YET_VALID => VerifyResult::NotYetValid,
+            openssl_sys::X509_V_ERR_CERT_REVOKED => VerifyResult::Revoked,
+            code => {
+                VerifyResult::Other {
+                    code,
+                    reason: error.error_string().to_owned(),
+                }
+            }
+        }
+    }
+
+    /// Returns true if the chain leads to a trusted root.
+    pub fn is_trusted(&self) -> bool {
+        matches!(*self, VerifyResult::Trusted(_))
+    }
+}
+
+/// Builds a store holding only the custom trust anchors.
+pub(crate) fn custom_store(files: &[PathBuf], dirs: &[PathBuf]) -> Result<X509Store> {
+    let mut store = X509StoreBuilder::new()?;
+    if !files.is_empty() {
+        let lookup = store.add_lookup(X509Lookup::file())?;
+        for file in files {
+            lookup.load_cert_file(file, SslFiletype::PEM)?;
+        }
+    }
+    if !dirs.is_empty() {
+        let lookup = store.add_lookup(X509Lookup::hash_dir())?;
+        for dir in dirs {
+            lookup.add_dir(&dir.to_string_lossy(), SslFiletype::PEM)?;
+        }
+    }
+    Ok(store.build())
+}
+
+/// Returns true if `chain`, starting with the leaf certificate, leads to a
+/// root of `store`.
+pub(crate) fn is_trusted_by(store: &X509StoreRef, chain: &StackRef<X509>) -> Result<bool> {
+    let leaf = match chain.get(0) {
+        Some(leaf) => leaf,
+        None => return Ok(false),
+    };
+    let mut context = X509StoreContext::new()?;
+    Ok(context.init(store, leaf, chain, |c| c.verify_cert())?)
+}
+
+impl fmt::Display for VerifyResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VerifyResult::Trusted(TrustAnchor::System) => write!(f, "trusted"),
+            VerifyResult::Trusted(TrustAnchor::Custom) => write!(f, "trusted by custom CA"),
+            VerifyResult::UntrustedRoot => write!(f, "untrusted root certificate"),
+            VerifyResult::MissingIntermediate => write!(f, "issuer not found"),
+            VerifyResult::SelfSigned => write!(f, "self-signed certificate"),
+            VerifyResult::Expired => write!(f, "certificate expired"),
+            VerifyResult::NotYetValid => write!(f, "certificate not yet valid"),
+            VerifyResult::Revoked => write!(f, "certificate revoked"),
+            VerifyResult::Other { ref reason, .. } => write!(f, "{}", reason),
+        }
+    }
+}